@@ -0,0 +1,195 @@
+//! Raw backups of the memory of all pedals
+//!
+//! A backup contains the data bytes of every pedal exactly as the device
+//! returned them, including bytes that `PedalConfig` does not understand. It is
+//! stored as text with hexadecimal bytes:
+//!
+//! ```text
+//! # footswitch-rs backup
+//! version 1
+//! pedal 0: 08 81 00 04 00 00 00 00
+//! pedal 1: 0d 04 0b 08 0f 0f 12 2c 1a 12 15 0f 07 00 00 00
+//! pedal 2: 08 00 00 00 00 00 00 00
+//! ```
+//!
+//! or, if the file name ends with `.bin`, in binary: the magic bytes `FSRS`,
+//! the version, and per pedal its number, the number of bytes, and the bytes.
+
+use std::fs;
+use std::path::Path;
+use protocol;
+use error::{FootswitchError, Result};
+
+/// Version of the backup format that is written
+pub const VERSION: u8 = 1;
+
+const MAGIC: &[u8] = b"FSRS";
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Backup {
+    /// Data bytes of every pedal, indexed by the number of the pedal
+    pub pedals: Vec<Vec<u8>>,
+}
+
+impl Backup {
+    pub fn load(path: &Path) -> Result<Backup> {
+        let content = fs::read(path)?;
+
+        let backup = if is_binary(path) {
+            Backup::from_bytes(&content)
+        }
+        else {
+            Backup::from_text(&String::from_utf8_lossy(&content))
+        };
+
+        backup.map_err(|e| FootswitchError::InvalidBackup(format!("Could not parse {}: {}", path.display(), e)))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if is_binary(path) {
+            fs::write(path, self.to_bytes())?;
+        }
+        else {
+            fs::write(path, self.to_text())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("# footswitch-rs backup\nversion {}\n", VERSION);
+
+        for (ped, data) in self.pedals.iter().enumerate() {
+            text.push_str(&format!("pedal {}: {}\n", ped, protocol::to_hex(data)));
+        }
+
+        text
+    }
+
+    pub fn from_text(text: &str) -> ::std::result::Result<Backup, String> {
+        let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        match lines.next() {
+            Some(line) if line.starts_with("version ") => check_version(line["version ".len()..].trim().parse().ok())?,
+            _ => return Err("The backup does not start with a version.".to_string()),
+        }
+
+        let mut pedals = Vec::new();
+
+        for line in lines {
+            let mut parts = line.splitn(2, ':');
+
+            let ped = parts.next().and_then(|part| part.trim().strip_prefix("pedal "))
+                .and_then(|ped| ped.trim().parse::<usize>().ok());
+
+            let data = parts.next().and_then(protocol::from_hex);
+
+            match (ped, data) {
+                (Some(ped), Some(data)) if ped == pedals.len() => pedals.push(data),
+                _ => return Err(format!("Invalid line '{}'.", line)),
+            }
+        }
+
+        Backup::new(pedals)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+
+        for (ped, data) in self.pedals.iter().enumerate() {
+            bytes.push(ped as u8);
+            bytes.push(data.len() as u8);
+            bytes.extend_from_slice(data);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> ::std::result::Result<Backup, String> {
+        if !bytes.starts_with(MAGIC) {
+            return Err("The file is not a footswitch-rs backup.".to_string());
+        }
+
+        check_version(bytes.get(MAGIC.len()).cloned())?;
+
+        let mut pedals = Vec::new();
+        let mut rest = &bytes[(MAGIC.len() + 1)..];
+
+        while !rest.is_empty() {
+            if rest.len() < 2 || rest[0] as usize != pedals.len() || rest.len() < 2 + rest[1] as usize {
+                return Err("The backup is truncated or corrupt.".to_string());
+            }
+
+            let len = rest[1] as usize;
+            pedals.push(rest[2..(2 + len)].to_vec());
+            rest = &rest[(2 + len)..];
+        }
+
+        Backup::new(pedals)
+    }
+
+    fn new(pedals: Vec<Vec<u8>>) -> ::std::result::Result<Backup, String> {
+        if pedals.len() != protocol::NUM_PEDALS {
+            return Err(format!("The backup contains {} pedals instead of {}.", pedals.len(), protocol::NUM_PEDALS));
+        }
+
+        Ok(Backup { pedals })
+    }
+}
+
+fn check_version(version: Option<u8>) -> ::std::result::Result<(), String> {
+    match version {
+        Some(VERSION) => Ok(()),
+        Some(version) => Err(format!("Unsupported backup version {}. This version of footswitch-rs supports version {}.", version, VERSION)),
+        None => Err("The backup has no valid version.".to_string()),
+    }
+}
+
+fn is_binary(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "bin")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Backup {
+        Backup {
+            pedals: vec![
+                vec![0x08, 0x81, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00],
+                vec![0x0d, 0x04, 0x0b, 0x08, 0x0f, 0x0f, 0x12, 0x2c, 0x1a, 0x12, 0x15, 0x0f, 0x07, 0x00, 0x00, 0x00],
+                vec![0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ],
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        assert_eq!(Backup::from_text(&example().to_text()).unwrap(), example());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        assert_eq!(Backup::from_bytes(&example().to_bytes()).unwrap(), example());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let text = example().to_text().replace("version 1", "version 2");
+        assert!(Backup::from_text(&text).is_err());
+
+        let mut bytes = example().to_bytes();
+        bytes[MAGIC.len()] = 2;
+        assert!(Backup::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_backups() {
+        let bytes = example().to_bytes();
+        assert!(Backup::from_bytes(&bytes[..(bytes.len() - 1)]).is_err());
+
+        let text = example().to_text().replace("pedal 2: 08 00 00 00 00 00 00 00\n", "");
+        assert!(Backup::from_text(&text).is_err());
+    }
+}
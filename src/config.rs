@@ -0,0 +1,159 @@
+//! User configuration
+//!
+//! The configuration is read from `$XDG_CONFIG_HOME/footswitch-rs/config.toml`
+//! (or `~/.config/footswitch-rs/config.toml` if `XDG_CONFIG_HOME` is not set).
+//! It can be used to add devices to the table of supported devices, and to
+//! choose the profiles that `watch` applies:
+//!
+//! ```toml
+//! [[device]]
+//! vendor_id = 0x1a86
+//! product_id = 0xe026
+//! interface = 1          # optional, defaults to 1
+//! protocol = "pcsensor"  # optional, defaults to "pcsensor" (the only supported protocol)
+//!
+//! [watch]
+//! profile = "default"    # optional, for foot switches without an entry below
+//!
+//! [watch.serial]
+//! "A1B2C3" = "transcription"
+//!
+//! [daemon]
+//! keys = ["f13", "f14", "f15"]  # optional, keys that the pedals are programmed to
+//!
+//! [[daemon.pedal]]
+//! pedal = 2
+//! press = "git commit"    # optional
+//! release = "notify-send released"  # optional
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use toml;
+use devices::DeviceId;
+use daemon;
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Devices that are added to the built-in table of supported devices
+    #[serde(default)]
+    pub device: Vec<DeviceId>,
+
+    /// Profiles that are applied to foot switches that are plugged in
+    #[serde(default)]
+    pub watch: Watch,
+
+    /// Commands that `daemon` runs when pedals are pressed
+    #[serde(default)]
+    pub daemon: Daemon,
+}
+
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Watch {
+    /// Profile (a file or a named profile) for foot switches without an entry in `serial`
+    pub profile: Option<String>,
+
+    /// Profiles by serial number of the foot switch
+    #[serde(default)]
+    pub serial: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Daemon {
+    /// Keys that the pedals are programmed to, one per pedal
+    #[serde(default = "default_keys")]
+    pub keys: Vec<String>,
+
+    #[serde(default)]
+    pub pedal: Vec<daemon::Binding>,
+}
+
+impl Default for Daemon {
+    fn default() -> Daemon {
+        Daemon { keys: default_keys(), pedal: Vec::new() }
+    }
+}
+
+fn default_keys() -> Vec<String> {
+    daemon::DEFAULT_KEYS.iter().map(|key| key.to_string()).collect()
+}
+
+impl Watch {
+    /// Profile for a foot switch, if there is one
+    pub fn profile_for(&self, serial: Option<&str>) -> Option<&str> {
+        serial.and_then(|serial| self.serial.get(serial))
+            .or(self.profile.as_ref())
+            .map(|profile| profile.as_str())
+    }
+}
+
+/// Directory in which all configuration of footswitch-rs is stored
+pub fn config_dir() -> PathBuf {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".config"),
+    };
+
+    base.join("footswitch-rs")
+}
+
+pub fn config_file() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+/// Loads the configuration. A missing file yields the default configuration.
+pub fn load(path: &Path) -> Result<Config, String> {
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+
+    toml::from_str(&content)
+        .map_err(|e| format!("Could not parse {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devices::{DeviceTable, Protocol};
+
+    #[test]
+    fn parses_devices_with_defaults() {
+        let config: Config = toml::from_str("[[device]]\nvendor_id = 0x1a86\nproduct_id = 0xe026\n\n[[device]]\nvendor_id = 0x0c45\nproduct_id = 0x7403\ninterface = 0\nprotocol = \"pcsensor\"\n").unwrap();
+
+        assert_eq!(config.device, vec![
+            DeviceId { vendor_id: 0x1a86, product_id: 0xe026, interface: 1, protocol: Protocol::PcSensor },
+            DeviceId { vendor_id: 0x0c45, product_id: 0x7403, interface: 0, protocol: Protocol::PcSensor },
+        ]);
+
+        // Devices of the configuration replace built-in entries
+        let mut table = DeviceTable::default();
+        for id in config.device {
+            table.add(id);
+        }
+
+        assert_eq!(table.find(0x0c45, 0x7403).map(|id| id.interface), Some(0));
+        assert!(table.is_supported(0x1a86, 0xe026));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(toml::from_str::<Config>("[devices]\nvendor_id = 0x1a86\n").is_err());
+        assert!(toml::from_str::<Config>("[watch]\nprofiles = \"default\"\n").is_err());
+        assert!(toml::from_str::<Config>("[daemon]\nkey = [\"f13\"]\n").is_err());
+        assert!(toml::from_str::<Config>("").is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_protocols() {
+        let err = toml::from_str::<Config>("[[device]]\nvendor_id = 0x1a86\nproduct_id = 0xe026\nprotocol = \"other\"\n").unwrap_err();
+        assert!(err.to_string().contains("Unknown protocol 'other'. Supported protocols: pcsensor."));
+    }
+}
@@ -0,0 +1,245 @@
+//! Running commands when pedals are pressed
+//!
+//! The pedals themselves can only send a key, a mouse action, or a short
+//! string. For everything else, every pedal is programmed to a key that no
+//! keyboard has (F13 to F24). A `Daemon` reads these keys from a `KeySource`,
+//! e.g., the exclusively grabbed event device of the foot switch (see
+//! `evdev`), and runs the shell commands that are bound to the pedal.
+//!
+//! The commands get the following environment variables:
+//!
+//!  * `FOOTSWITCH_PEDAL`: number of the pedal
+//!  * `FOOTSWITCH_ACTION`: `press` or `release`
+//!  * `FOOTSWITCH_DURATION_MS`: how long the pedal was held, in milliseconds (0 when pressed)
+
+use std::io;
+use std::thread;
+use std::process;
+use std::time::Duration;
+use key_operations;
+use listen::Action;
+
+/// Keys that the pedals are programmed to by default
+pub const DEFAULT_KEYS: &[&str] = &["f13", "f14", "f15"];
+
+/// HID usage of F13; F13 to F24 are consecutive
+const HID_F13: u8 = 0x68;
+const HID_F24: u8 = 0x73;
+
+/// Linux key code of F13 (KEY_F13 in linux/input-event-codes.h); F13 to F24 are consecutive
+const LINUX_F13: u16 = 183;
+
+/// Commands that are bound to a pedal
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Binding {
+    pub pedal: usize,
+
+    /// Command that is run when the pedal is pressed
+    pub press: Option<String>,
+
+    /// Command that is run when the pedal is released
+    pub release: Option<String>,
+}
+
+/// A key was pressed or released
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyEvent {
+    /// Linux key code
+    pub code: u16,
+    pub pressed: bool,
+
+    /// Time of the event, on any clock
+    pub time: Duration,
+}
+
+/// Source of key events. Key repeats must not be reported.
+pub trait KeySource {
+    /// Wait for the next event
+    fn next_event(&mut self) -> io::Result<KeyEvent>;
+}
+
+/// Runs the commands of the bindings
+pub trait CommandRunner {
+    fn run(&mut self, command: &str, env: &[(&str, String)]) -> io::Result<()>;
+}
+
+/// Runs commands with `sh -c`, without waiting for them to finish
+pub struct ShellRunner;
+
+impl CommandRunner for ShellRunner {
+    fn run(&mut self, command: &str, env: &[(&str, String)]) -> io::Result<()> {
+        let mut child = process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .stdin(process::Stdio::null())
+            .spawn()?;
+
+        // Reap the child when it finishes, so that it does not linger as a zombie
+        thread::spawn(move || child.wait());
+
+        Ok(())
+    }
+}
+
+/// Linux key code of a key that pedals can be programmed to for the daemon
+pub fn linux_keycode(key: &str) -> Option<u16> {
+    match key_operations::encode_byte(key) {
+        Some(usage) if (HID_F13..=HID_F24).contains(&usage) => Some(LINUX_F13 + u16::from(usage - HID_F13)),
+        _ => None,
+    }
+}
+
+/// What the daemon did with an event of a pedal
+#[derive(Debug)]
+pub struct Handled {
+    pub pedal: usize,
+    pub action: Action,
+
+    /// How long the pedal was held (zero when it was pressed)
+    pub duration: Duration,
+
+    /// Command that was run, if any, and whether it could be started
+    pub command: Option<(String, io::Result<()>)>,
+}
+
+pub struct Daemon<S: KeySource, R: CommandRunner> {
+    source: S,
+    runner: R,
+
+    /// Key code that every pedal sends, indexed by the number of the pedal
+    codes: Vec<u16>,
+    bindings: Vec<Binding>,
+
+    /// Time at which every pedal was pressed, if it is held
+    pressed: Vec<Option<Duration>>,
+}
+
+impl<S: KeySource, R: CommandRunner> Daemon<S, R> {
+    pub fn new(source: S, runner: R, codes: Vec<u16>, bindings: Vec<Binding>) -> Daemon<S, R> {
+        let pressed = vec![None; codes.len()];
+
+        Daemon { source, runner, codes, bindings, pressed }
+    }
+
+    /// Wait for the next event and run the command that is bound to it. Returns
+    /// `None` for events of keys that do not belong to a pedal.
+    pub fn step(&mut self) -> io::Result<Option<Handled>> {
+        let event = self.source.next_event()?;
+
+        let pedal = match self.codes.iter().position(|code| *code == event.code) {
+            Some(pedal) => pedal,
+            None => return Ok(None),
+        };
+
+        let (action, duration) = if event.pressed {
+            self.pressed[pedal] = Some(event.time);
+
+            (Action::Press, Duration::default())
+        }
+        else {
+            // Without a press (e.g., when the daemon started while the pedal was held), the duration is unknown
+            let start = self.pressed[pedal].take().unwrap_or(event.time);
+
+            (Action::Release, event.time.checked_sub(start).unwrap_or_default())
+        };
+
+        let command = self.bindings.iter()
+            .find(|binding| binding.pedal == pedal)
+            .and_then(|binding| match action {
+                Action::Press => binding.press.clone(),
+                Action::Release => binding.release.clone(),
+            });
+
+        let command = command.map(|command| {
+            let env = [
+                ("FOOTSWITCH_PEDAL", pedal.to_string()),
+                ("FOOTSWITCH_ACTION", match action { Action::Press => "press", Action::Release => "release" }.to_string()),
+                ("FOOTSWITCH_DURATION_MS", duration.as_millis().to_string()),
+            ];
+
+            let result = self.runner.run(&command, &env);
+            (command, result)
+        });
+
+        Ok(Some(Handled { pedal, action, duration, command }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    struct MockSource(VecDeque<KeyEvent>);
+
+    impl KeySource for MockSource {
+        fn next_event(&mut self) -> io::Result<KeyEvent> {
+            self.0.pop_front().ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "No events left"))
+        }
+    }
+
+    #[derive(Default)]
+    struct MockRunner(Vec<(String, Vec<(String, String)>)>);
+
+    impl CommandRunner for MockRunner {
+        fn run(&mut self, command: &str, env: &[(&str, String)]) -> io::Result<()> {
+            self.0.push((command.to_string(), env.iter().map(|(key, value)| (key.to_string(), value.clone())).collect()));
+            Ok(())
+        }
+    }
+
+    fn event(code: u16, pressed: bool, millis: u64) -> KeyEvent {
+        KeyEvent { code, pressed, time: Duration::from_millis(millis) }
+    }
+
+    #[test]
+    fn maps_f_keys() {
+        assert_eq!(linux_keycode("f13"), Some(183));
+        assert_eq!(linux_keycode("f15"), Some(185));
+        assert_eq!(linux_keycode("f24"), Some(194));
+        assert_eq!(linux_keycode("a"), None);
+    }
+
+    #[test]
+    fn runs_commands_with_pedal_and_duration() {
+        let source = MockSource(vec![
+            event(184, true, 1000),
+            event(30, true, 1100),
+            event(184, false, 1250),
+            event(183, true, 2000),
+        ].into_iter().collect());
+
+        let bindings = vec![
+            Binding { pedal: 1, press: Some("echo press".to_string()), release: Some("echo release".to_string()) },
+        ];
+
+        let mut daemon = Daemon::new(source, MockRunner::default(), vec![183, 184, 185], bindings);
+
+        let handled = daemon.step().unwrap().unwrap();
+        assert_eq!((handled.pedal, handled.action), (1, Action::Press));
+
+        // Not a key of a pedal
+        assert!(daemon.step().unwrap().is_none());
+
+        let handled = daemon.step().unwrap().unwrap();
+        assert_eq!((handled.pedal, handled.action, handled.duration), (1, Action::Release, Duration::from_millis(250)));
+
+        // No command is bound to pedal 0
+        let handled = daemon.step().unwrap().unwrap();
+        assert_eq!(handled.pedal, 0);
+        assert!(handled.command.is_none());
+
+        let env = |pedal: &str, action: &str, duration: &str| vec![
+            ("FOOTSWITCH_PEDAL".to_string(), pedal.to_string()),
+            ("FOOTSWITCH_ACTION".to_string(), action.to_string()),
+            ("FOOTSWITCH_DURATION_MS".to_string(), duration.to_string()),
+        ];
+
+        assert_eq!(daemon.runner.0, vec![
+            ("echo press".to_string(), env("1", "press", "0")),
+            ("echo release".to_string(), env("1", "release", "250")),
+        ]);
+    }
+}
@@ -0,0 +1,565 @@
+//! Discovery of supported foot switches
+
+use std::io;
+use std::fs;
+use std::fs::OpenOptions;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use hidapi;
+use serde::{de, Deserialize, Deserializer};
+use error;
+use error::FootswitchError;
+use udev_rule;
+
+/// Protocol variants that are understood by footswitch-rs. Only `pcsensor` is
+/// supported for now; other values are rejected when a device is added.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// The configuration protocol of PCSensor foot switches (see `protocol`)
+    #[serde(rename = "pcsensor")]
+    PcSensor,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Protocol, String> {
+        match s {
+            "pcsensor" => Ok(Protocol::PcSensor),
+            _ => Err(format!("Unknown protocol '{}'. Supported protocols: pcsensor.", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Protocol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Protocol, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+/// A device that is supported, together with the interface that carries the
+/// configuration protocol
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct DeviceId {
+    pub vendor_id: u16,
+    pub product_id: u16,
+
+    #[serde(default = "default_interface")]
+    pub interface: i32,
+
+    #[serde(default = "default_protocol")]
+    pub protocol: Protocol,
+}
+
+fn default_interface() -> i32 { 1 }
+fn default_protocol() -> Protocol { Protocol::PcSensor }
+
+impl FromStr for DeviceId {
+    type Err = String;
+
+    /// Parses `vendorId:productId[:interface[:protocol]]`, with hexadecimal IDs
+    fn from_str(s: &str) -> Result<DeviceId, String> {
+        let parts: Vec<&str> = s.split(':').collect();
+
+        if parts.len() < 2 || parts.len() > 4 {
+            return Err(format!("Invalid device '{}'. Use vendorId:productId[:interface[:protocol]].", s));
+        }
+
+        let parse_id = |id: &str| u16::from_str_radix(id, 16).map_err(|_| format!("Invalid hexadecimal ID '{}'", id));
+
+        Ok(DeviceId {
+            vendor_id: parse_id(parts[0])?,
+            product_id: parse_id(parts[1])?,
+            interface: match parts.get(2) {
+                Some(interface) => interface.parse().map_err(|_| format!("Invalid interface number '{}'", interface))?,
+                None => default_interface(),
+            },
+            protocol: match parts.get(3) {
+                Some(protocol) => protocol.parse()?,
+                None => default_protocol(),
+            },
+        })
+    }
+}
+
+/// Bus type of USB devices in the HID_ID of the kernel
+const BUS_USB: u16 = 0x03;
+
+/// Interface through which foot switches send the keys (and mouse events) of the pedals
+pub const KEYBOARD_INTERFACE: i32 = 0;
+
+/// All `(vendorId, productId)` combinations that are supported out of the box
+pub static SUPPORTED_DEVICES: &[(u16, u16)] = &[
+    (0x0c45, 0x7403),
+    (0x0c45, 0x7404),
+    (0x413d, 0x2107),
+];
+
+/// Table of all devices that footswitch-rs may use
+#[derive(Debug, Clone)]
+pub struct DeviceTable {
+    ids: Vec<DeviceId>,
+}
+
+impl Default for DeviceTable {
+    fn default() -> DeviceTable {
+        DeviceTable {
+            ids: SUPPORTED_DEVICES.iter().map(|&(vendor_id, product_id)| DeviceId {
+                vendor_id,
+                product_id,
+                interface: default_interface(),
+                protocol: default_protocol(),
+            }).collect()
+        }
+    }
+}
+
+impl DeviceTable {
+    /// Add a device to the table. Entries that are added later take precedence.
+    pub fn add(&mut self, id: DeviceId) {
+        self.ids.retain(|other| (other.vendor_id, other.product_id) != (id.vendor_id, id.product_id));
+        self.ids.push(id);
+    }
+
+    pub fn ids(&self) -> &[DeviceId] {
+        &self.ids
+    }
+
+    /// Returns the entry of a device, if it is supported
+    pub fn find(&self, vendor_id: u16, product_id: u16) -> Option<&DeviceId> {
+        self.ids.iter().find(|id| (id.vendor_id, id.product_id) == (vendor_id, product_id))
+    }
+
+    pub fn is_supported(&self, vendor_id: u16, product_id: u16) -> bool {
+        self.find(vendor_id, product_id).is_some()
+    }
+
+    /// Checks if a device is supported and if the interface carries the configuration protocol
+    pub fn is_config_interface(&self, device: &hidapi::HidDeviceInfo) -> bool {
+        match self.find(device.vendor_id, device.product_id) {
+            Some(id) => id.interface == device.interface_number,
+            None => false,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeviceInfo {
+    pub vendor_id: String,
+    pub product_id: String,
+    pub interface_number: i32,
+    pub config_interface: bool,
+    pub protocol: Protocol,
+    pub path: String,
+    pub hidraw: Option<String>,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub accessible: bool,
+}
+
+/// Returns all interfaces of all attached devices that are supported
+pub fn list_devices(table: &DeviceTable) -> error::Result<Vec<DeviceInfo>> {
+    let api = hidapi::HidApi::new()
+        .map_err(|e| FootswitchError::Io(io::Error::other(format!("Could not initialize HID object: {}", e))))?;
+
+    Ok(enumerate(&api, table).iter()
+        .filter_map(|device| table.find(device.vendor_id, device.product_id).map(|id| (device, id)))
+        .map(|(device, id)| {
+            let path = device.path.to_string_lossy().into_owned();
+            let hidraw = hidraw_node(&path);
+
+            DeviceInfo {
+                vendor_id: format!("{:04x}", device.vendor_id),
+                product_id: format!("{:04x}", device.product_id),
+                interface_number: device.interface_number,
+                config_interface: id.interface == device.interface_number,
+                protocol: id.protocol,
+                accessible: is_accessible(&path, &hidraw),
+                path,
+                hidraw,
+                serial_number: device.serial_number.clone(),
+                manufacturer: device.manufacturer_string.clone(),
+                product: device.product_string.clone(),
+            }
+        })
+        .collect())
+}
+
+/// All HID devices that hidapi lists, followed by the virtual devices of the table
+pub fn enumerate(api: &hidapi::HidApi, table: &DeviceTable) -> Vec<hidapi::HidDeviceInfo> {
+    let mut devices = api.devices().clone();
+
+    for device in virtual_devices(table) {
+        if !devices.iter().any(|other| other.path == device.path) {
+            devices.push(device);
+        }
+    }
+
+    devices
+}
+
+/// Supported devices that were created through uhid (see `uhid`), e.g., by
+/// `footswitch-rs emulate`. They claim to be USB devices, but have no USB
+/// parent, so the libusb backend of hidapi does not list them and the hidraw
+/// backend drops them. Like for USB devices, the interface number is the
+/// number at the end of the physical path (`.../input1`).
+pub fn virtual_devices(table: &DeviceTable) -> Vec<hidapi::HidDeviceInfo> {
+    virtual_devices_in(Path::new("/sys/class/hidraw"), table)
+}
+
+fn virtual_devices_in(class_dir: &Path, table: &DeviceTable) -> Vec<hidapi::HidDeviceInfo> {
+    let entries = match fs::read_dir(class_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut devices: Vec<hidapi::HidDeviceInfo> = entries.filter_map(|e| e.ok())
+        .filter_map(|entry| {
+            let hid_dev = fs::canonicalize(entry.path().join("device")).ok()?;
+
+            if hid_dev.ancestors().any(|dir| dir.join("busnum").exists()) {
+                return None;
+            }
+
+            let uevent = fs::read_to_string(hid_dev.join("uevent")).ok()?;
+            let property = |key: &str| uevent.lines()
+                .find(|line| line.starts_with(&format!("{}=", key)))
+                .map(|line| line[(key.len() + 1)..].to_string());
+
+            // HID_ID=0003:00000C45:00007403 (bus, vendor, product)
+            let id = property("HID_ID")?;
+            let parts: Vec<&str> = id.split(':').collect();
+
+            if parts.len() != 3 || u16::from_str_radix(parts[0], 16).ok()? != BUS_USB {
+                return None;
+            }
+
+            let vendor_id = u16::from_str_radix(parts[1], 16).ok()?;
+            let product_id = u16::from_str_radix(parts[2], 16).ok()?;
+
+            if !table.is_supported(vendor_id, product_id) {
+                return None;
+            }
+
+            let interface_number = property("HID_PHYS")
+                .and_then(|phys| phys.rsplit("/input").next().and_then(|number| number.parse().ok()))
+                .unwrap_or(-1);
+
+            Some(hidapi::HidDeviceInfo {
+                path: CString::new(format!("/dev/{}", entry.file_name().to_string_lossy())).ok()?,
+                vendor_id,
+                product_id,
+                serial_number: property("HID_UNIQ").filter(|uniq| !uniq.is_empty()),
+                release_number: 0,
+                manufacturer_string: None,
+                product_string: property("HID_NAME"),
+                usage_page: 0,
+                usage: 0,
+                interface_number,
+            })
+        })
+        .collect();
+
+    devices.sort_by(|a, b| a.path.cmp(&b.path));
+    devices
+}
+
+/// Checks if a device path is a hidraw node, like the paths of virtual devices
+pub fn is_hidraw_path(path: &str) -> bool {
+    path.starts_with("/dev/hidraw")
+}
+
+/// Checks if the current user may read from and write to the device, without
+/// talking to the device itself.
+pub fn is_accessible(path: &str, hidraw: &Option<String>) -> bool {
+    OpenOptions::new().read(true).write(true).open(device_node(path, hidraw)).is_ok()
+}
+
+/// Checks if the current user may read from and write to the device node of a
+/// device. If not, the error explains how to grant access.
+pub fn check_access(path: &str) -> error::Result<()> {
+    let node = device_node(path, &hidraw_node(path));
+
+    match OpenOptions::new().read(true).write(true).open(&node) {
+        Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => Err(FootswitchError::PermissionDenied(
+            format!("You are not allowed to read from and write to {}. {}", node, udev_rule::install_instructions()))),
+
+        // Other problems (e.g., platforms without device nodes) show up when the device is opened
+        _ => Ok(()),
+    }
+}
+
+/// File through which hidapi accesses a device. The libusb backend opens the
+/// USB device node even if the device also has a hidraw node.
+pub fn device_node(path: &str, hidraw: &Option<String>) -> String {
+    match (parse_libusb_path(path), hidraw) {
+        (Some((bus, address, _)), _) => format!("/dev/bus/usb/{:03}/{:03}", bus, address),
+        (None, Some(hidraw)) => hidraw.clone(),
+        (None, None) => path.to_string(),
+    }
+}
+
+/// Paths of the libusb backend have the format `bus:address:interface` (hexadecimal)
+fn parse_libusb_path(path: &str) -> Option<(u32, u32, u32)> {
+    let parts: Vec<&str> = path.split(':').collect();
+
+    if parts.len() != 3 {
+        return None;
+    }
+
+    let bus = u32::from_str_radix(parts[0], 16).ok()?;
+    let address = u32::from_str_radix(parts[1], 16).ok()?;
+    let interface = u32::from_str_radix(parts[2], 16).ok()?;
+
+    Some((bus, address, interface))
+}
+
+/// Find the hidraw node that belongs to a device path of hidapi
+pub fn hidraw_node(path: &str) -> Option<String> {
+    if is_hidraw_path(path) {
+        return Some(path.to_string());
+    }
+
+    let (bus, address, interface) = parse_libusb_path(path)?;
+
+    // /sys/class/hidraw/hidrawN/device points to .../<usb device>/<usb interface>/<hid device>
+    for entry in fs::read_dir("/sys/class/hidraw").ok()?.filter_map(|e| e.ok()) {
+        let hid_dev = match fs::canonicalize(entry.path().join("device")) {
+            Ok(hid_dev) => hid_dev,
+            Err(_) => continue,
+        };
+
+        let intf_dev = match hid_dev.parent() {
+            Some(intf_dev) => intf_dev,
+            None => continue,
+        };
+
+        let usb_dev = match intf_dev.parent() {
+            Some(usb_dev) => usb_dev,
+            None => continue,
+        };
+
+        if read_sysfs_number(usb_dev, "busnum", 10) == Some(bus)
+            && read_sysfs_number(usb_dev, "devnum", 10) == Some(address)
+            && read_sysfs_number(intf_dev, "bInterfaceNumber", 16) == Some(interface) {
+            return Some(format!("/dev/{}", entry.file_name().to_string_lossy()));
+        }
+    }
+
+    None
+}
+
+/// A supported device on the USB bus, as the kernel sees it (Linux only).
+/// Unlike `list_devices`, this does not depend on hidapi or on a driver.
+#[derive(Debug, Clone)]
+pub struct UsbDevice {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bus: u32,
+    pub address: u32,
+
+    /// Directory of the device in sysfs
+    pub sysfs: PathBuf,
+}
+
+impl UsbDevice {
+    /// Directory of an interface of the device in sysfs, if the device has it
+    pub fn interface(&self, number: i32) -> Option<PathBuf> {
+        let name = self.sysfs.file_name()?.to_string_lossy().to_string();
+
+        fs::read_dir(&self.sysfs).ok()?
+            .filter_map(|e| e.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.file_name().is_some_and(|file| file.to_string_lossy().starts_with(&format!("{}:", name))))
+            .find(|path| read_sysfs_number(path, "bInterfaceNumber", 16) == Some(number as u32))
+    }
+}
+
+/// Returns all supported devices on the USB bus
+pub fn usb_devices(table: &DeviceTable) -> io::Result<Vec<UsbDevice>> {
+    let mut devices = Vec::new();
+
+    for entry in fs::read_dir("/sys/bus/usb/devices")?.filter_map(|e| e.ok()) {
+        let dir = entry.path();
+
+        let (vendor_id, product_id) = match (read_sysfs_number(&dir, "idVendor", 16), read_sysfs_number(&dir, "idProduct", 16)) {
+            (Some(vendor_id), Some(product_id)) => (vendor_id as u16, product_id as u16),
+            _ => continue,
+        };
+
+        if !table.is_supported(vendor_id, product_id) {
+            continue;
+        }
+
+        devices.push(UsbDevice {
+            vendor_id,
+            product_id,
+            bus: read_sysfs_number(&dir, "busnum", 10).unwrap_or(0),
+            address: read_sysfs_number(&dir, "devnum", 10).unwrap_or(0),
+            sysfs: dir,
+        });
+    }
+
+    Ok(devices)
+}
+
+/// Name of the kernel driver that claimed an interface (see `UsbDevice::interface`)
+pub fn interface_driver(interface: &Path) -> Option<String> {
+    let driver = fs::read_link(interface.join("driver")).ok()?;
+
+    Some(driver.file_name()?.to_string_lossy().to_string())
+}
+
+/// Bus number and address of the USB device to which a device path of hidapi belongs
+pub fn usb_address(path: &str) -> Option<(u32, u32)> {
+    if let Some((bus, address, _)) = parse_libusb_path(path) {
+        return Some((bus, address));
+    }
+
+    if !path.starts_with("/dev/hidraw") {
+        return None;
+    }
+
+    // See hidraw_node
+    let hid_dev = fs::canonicalize(Path::new("/sys/class/hidraw").join(Path::new(path).file_name()?).join("device")).ok()?;
+    let usb_dev = hid_dev.parent()?.parent()?;
+
+    Some((read_sysfs_number(usb_dev, "busnum", 10)?, read_sysfs_number(usb_dev, "devnum", 10)?))
+}
+
+/// Event devices (`/dev/input/event*`) of an interface of a USB device
+pub fn evdev_nodes(bus: u32, address: u32, interface: i32) -> Vec<String> {
+    let entries = match fs::read_dir("/sys/class/input") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut nodes: Vec<String> = entries.filter_map(|e| e.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("event"))
+        .filter(|entry| {
+            // .../<usb device>/<usb interface>/<hid device>/input/inputN
+            let input_dev = match fs::canonicalize(entry.path().join("device")) {
+                Ok(input_dev) => input_dev,
+                Err(_) => return false,
+            };
+
+            let intf_dev = match input_dev.ancestors().find(|dir| dir.join("bInterfaceNumber").exists()) {
+                Some(intf_dev) => intf_dev,
+                None => return false,
+            };
+
+            let usb_dev = match intf_dev.parent() {
+                Some(usb_dev) => usb_dev,
+                None => return false,
+            };
+
+            read_sysfs_number(intf_dev, "bInterfaceNumber", 16) == Some(interface as u32)
+                && read_sysfs_number(usb_dev, "busnum", 10) == Some(bus)
+                && read_sysfs_number(usb_dev, "devnum", 10) == Some(address)
+        })
+        .map(|entry| format!("/dev/input/{}", entry.file_name().to_string_lossy()))
+        .collect();
+
+    nodes.sort();
+    nodes
+}
+
+fn read_sysfs_number(dir: &Path, attribute: &str, radix: u32) -> Option<u32> {
+    let value = fs::read_to_string(dir.join(attribute)).ok()?;
+
+    u32::from_str_radix(value.trim(), radix).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::{env, process};
+    #[cfg(unix)]
+    use std::os::unix::fs::symlink;
+
+    /// Creates a hidraw node in a fake /sys/class/hidraw whose device has the given uevent
+    #[cfg(unix)]
+    fn add_hidraw(root: &Path, node: &str, device: &str, uevent: &str) {
+        let hid_dev = root.join(device);
+        fs::create_dir_all(&hid_dev).unwrap();
+        fs::write(hid_dev.join("uevent"), uevent).unwrap();
+
+        let class_dir = root.join("class/hidraw").join(node);
+        fs::create_dir_all(&class_dir).unwrap();
+        symlink(&hid_dev, class_dir.join("device")).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn enumerates_virtual_devices() {
+        let root = env::temp_dir().join(format!("footswitch-rs-sysfs-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        add_hidraw(&root, "hidraw0", "devices/virtual/misc/uhid/0003:0C45:7403.0001",
+                   "HID_ID=0003:00000C45:00007403\nHID_NAME=PCsensor FootSwitch (virtual)\nHID_PHYS=footswitch-rs-1/input0\nHID_UNIQ=\n");
+        add_hidraw(&root, "hidraw1", "devices/virtual/misc/uhid/0003:0C45:7403.0002",
+                   "HID_ID=0003:00000C45:00007403\nHID_NAME=PCsensor FootSwitch (virtual)\nHID_PHYS=footswitch-rs-1/input1\nHID_UNIQ=A1B2\n");
+
+        // Real USB devices are listed by hidapi
+        add_hidraw(&root, "hidraw2", "devices/usb1/1-2/1-2:1.1/0003:0C45:7403.0003",
+                   "HID_ID=0003:00000C45:00007403\nHID_PHYS=usb-0000:00:14.0-2/input1\n");
+        fs::write(root.join("devices/usb1/1-2/busnum"), "1\n").unwrap();
+
+        // Unsupported device
+        add_hidraw(&root, "hidraw3", "devices/virtual/misc/uhid/0003:046D:C52B.0004",
+                   "HID_ID=0003:0000046D:0000C52B\nHID_PHYS=other/input0\n");
+
+        let devices = virtual_devices_in(&root.join("class/hidraw"), &DeviceTable::default());
+        fs::remove_dir_all(&root).unwrap();
+
+        let found: Vec<(&str, u16, u16, i32, Option<String>)> = devices.iter()
+            .map(|device| (device.path.to_str().unwrap(), device.vendor_id, device.product_id, device.interface_number, device.serial_number.clone()))
+            .collect();
+
+        assert_eq!(found, vec![
+            ("/dev/hidraw0", 0x0c45, 0x7403, 0, None),
+            ("/dev/hidraw1", 0x0c45, 0x7403, 1, Some("A1B2".to_string())),
+        ]);
+
+        assert!(DeviceTable::default().is_config_interface(&devices[1]));
+    }
+
+    #[test]
+    fn libusb_paths_use_the_usb_device_node() {
+        let hidraw = Some("/dev/hidraw3".to_string());
+
+        assert_eq!(device_node("0001:000a:01", &hidraw), "/dev/bus/usb/001/010");
+        assert_eq!(device_node("0001:000a:01", &None), "/dev/bus/usb/001/010");
+        assert_eq!(device_node("/dev/hidraw3", &hidraw), "/dev/hidraw3");
+        assert_eq!(device_node("/dev/hidraw3", &None), "/dev/hidraw3");
+    }
+
+    #[test]
+    fn parses_device_ids() {
+        assert_eq!("1a86:E026".parse(), Ok(DeviceId { vendor_id: 0x1a86, product_id: 0xe026, interface: 1, protocol: Protocol::PcSensor }));
+        assert_eq!("1a86:e026:0:pcsensor".parse(), Ok(DeviceId { vendor_id: 0x1a86, product_id: 0xe026, interface: 0, protocol: Protocol::PcSensor }));
+
+        for invalid in &["1a86", "1a86e026", "1a86:e026:1:pcsensor:x", "xyz:e026", "1a86:10000", "1a86:e026:one", "1a86:e026:1:other", ""] {
+            assert!(invalid.parse::<DeviceId>().is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn added_devices_take_precedence() {
+        let mut table = DeviceTable::default();
+        let builtin = table.ids().len();
+
+        table.add("0c45:7403:2".parse().unwrap());
+        table.add("1a86:e026".parse().unwrap());
+        table.add("1a86:e026:0".parse().unwrap());
+
+        assert_eq!(table.ids().len(), builtin + 1);
+        assert_eq!(table.find(0x0c45, 0x7403).map(|id| id.interface), Some(2));
+        assert_eq!(table.find(0x1a86, 0xe026).map(|id| id.interface), Some(0));
+        assert!(table.is_supported(0x413d, 0x2107));
+        assert!(!table.is_supported(0x1a86, 0xe027));
+    }
+}
@@ -0,0 +1,318 @@
+//! Diagnosis of common problems with foot switches
+//!
+//! `diagnose` runs a series of checks, from the USB bus up to the protocol,
+//! and returns the result of every check together with a possible fix. Checks
+//! that depend on a check that failed are skipped.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use hidapi;
+use devices;
+use devices::DeviceTable;
+use pedal_operations::{self, DeviceSelector, Pedals};
+use pedal_config::PedalConfig;
+use error::FootswitchError;
+use protocol;
+use transport::Transport;
+use udev_rule;
+
+/// Directories in which udev looks for rules
+const RULE_DIRS: &[&str] = &["/etc/udev/rules.d", "/run/udev/rules.d", "/lib/udev/rules.d", "/usr/lib/udev/rules.d"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Ok,
+    Warning,
+    Failed,
+
+    /// The check could not run, because a check it depends on failed
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub message: String,
+
+    /// What the user can do about a problem
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, message: String) -> Check {
+        Check { name, status: Status::Ok, message, fix: None }
+    }
+
+    fn warning(name: &'static str, message: String, fix: String) -> Check {
+        Check { name, status: Status::Warning, message, fix: Some(fix) }
+    }
+
+    fn failed(name: &'static str, message: String, fix: String) -> Check {
+        Check { name, status: Status::Failed, message, fix: Some(fix) }
+    }
+
+    fn skipped(name: &'static str, message: &str) -> Check {
+        Check { name, status: Status::Skipped, message: message.to_string(), fix: None }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)
+    }
+}
+
+/// Run all checks for the devices of the table. The protocol is tested on
+/// the device that `selector` selects.
+pub fn diagnose(table: &DeviceTable, selector: &DeviceSelector) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let api = match hidapi::HidApi::new() {
+        Ok(api) => {
+            checks.push(Check::ok("hidapi", "Initialized hidapi.".to_string()));
+            Some(api)
+        },
+        Err(e) => {
+            checks.push(Check::failed("hidapi", format!("Could not initialize hidapi: {}.", e),
+                "Make sure that hidapi and its backend (hidraw or libusb) are installed.".to_string()));
+            None
+        },
+    };
+
+    let usb = check_usb(table, &mut checks);
+
+    // Configuration interfaces that hidapi can open
+    let interfaces: Vec<(String, Option<String>)> = match api {
+        Some(ref api) => {
+            let interfaces: Vec<_> = devices::enumerate(api, table).iter()
+                .filter(|device| table.is_config_interface(device))
+                .map(|device| {
+                    let path = device.path.to_string_lossy().to_string();
+                    let hidraw = devices::hidraw_node(&path);
+                    (path, hidraw)
+                })
+                .collect();
+
+            checks.push(check_interfaces(table, &usb, &interfaces));
+            interfaces
+        },
+        None => {
+            checks.push(Check::skipped("interface", "hidapi is not available."));
+            Vec::new()
+        },
+    };
+
+    // hidapi allows only one instance at a time, and the query opens its own
+    drop(api);
+
+    let accessible = check_permissions(&interfaces, &mut checks);
+
+    checks.push(check_udev_rules(table));
+    checks.push(check_drivers(table, &usb));
+
+    checks.push(if interfaces.is_empty() || !accessible {
+        Check::skipped("query", "No accessible foot switch.")
+    }
+    else {
+        check_query(table, selector)
+    });
+
+    checks
+}
+
+fn check_usb(table: &DeviceTable, checks: &mut Vec<Check>) -> Option<Vec<devices::UsbDevice>> {
+    match devices::usb_devices(table) {
+        Ok(ref usb) if usb.is_empty() => {
+            let ids: Vec<String> = table.ids().iter().map(|id| format!("{:04x}:{:04x}", id.vendor_id, id.product_id)).collect();
+
+            checks.push(Check::failed("usb", format!("None of the supported devices ({}) is on the USB bus.", ids.join(", ")),
+                "Connect the foot switch, or try another cable or USB port. If 'lsusb' shows it with another vendorId:productId, \
+                 add it with --add-device or the configuration file.".to_string()));
+            Some(Vec::new())
+        },
+        Ok(usb) => {
+            let found: Vec<String> = usb.iter()
+                .map(|device| format!("{:04x}:{:04x} (bus {:03}, device {:03})", device.vendor_id, device.product_id, device.bus, device.address))
+                .collect();
+
+            checks.push(Check::ok("usb", format!("Found {}.", found.join(", "))));
+            Some(usb)
+        },
+        Err(e) => {
+            checks.push(Check::skipped("usb", &format!("Could not read /sys/bus/usb/devices ({}).", e)));
+            None
+        },
+    }
+}
+
+fn check_interfaces(table: &DeviceTable, usb: &Option<Vec<devices::UsbDevice>>, interfaces: &[(String, Option<String>)]) -> Check {
+    if !interfaces.is_empty() {
+        let paths: Vec<String> = interfaces.iter().map(|(path, _)| path.clone()).collect();
+
+        return Check::ok("interface", format!("hidapi sees the configuration interface at {}.", paths.join(", ")));
+    }
+
+    match *usb {
+        Some(ref usb) if usb.is_empty() => Check::skipped("interface", "No foot switch is connected."),
+        _ => {
+            let interfaces: Vec<String> = table.ids().iter()
+                .map(|id| format!("{} of {:04x}:{:04x}", id.interface, id.vendor_id, id.product_id))
+                .collect();
+
+            Check::failed("interface", format!("hidapi does not see the configuration interface ({}).", interfaces.join(", ")),
+                "Check which interfaces 'footswitch-rs devices' lists. If the configuration interface has another number, \
+                 add the device with --add-device vendorId:productId:interface.".to_string())
+        },
+    }
+}
+
+fn check_permissions(interfaces: &[(String, Option<String>)], checks: &mut Vec<Check>) -> bool {
+    if interfaces.is_empty() {
+        checks.push(Check::skipped("permissions", "No configuration interface is visible."));
+        return false;
+    }
+
+    let denied: Vec<String> = interfaces.iter()
+        .filter(|(path, hidraw)| !devices::is_accessible(path, hidraw))
+        .map(|(path, hidraw)| devices::device_node(path, hidraw))
+        .collect();
+
+    if denied.is_empty() {
+        checks.push(Check::ok("permissions", "The current user may read from and write to the foot switch.".to_string()));
+        return true;
+    }
+
+    checks.push(Check::failed("permissions", format!("The current user may not read from and write to {}.", denied.join(", ")),
+        udev_rule::install_instructions()));
+
+    denied.len() < interfaces.len()
+}
+
+fn check_udev_rules(table: &DeviceTable) -> Check {
+    let rules: Vec<String> = RULE_DIRS.iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(|e| e.ok()).map(|entry| entry.path()))
+        .filter(|path| is_rule_for(path, table))
+        .map(|path| path.display().to_string())
+        .collect();
+
+    if rules.is_empty() {
+        return Check::warning("udev rule", "There is no udev rule for the foot switch, so only root may use it.".to_string(),
+            udev_rule::install_instructions());
+    }
+
+    Check::ok("udev rule", format!("Found {}.", rules.join(", ")))
+}
+
+/// Checks if a udev rule mentions one of the devices of the table
+fn is_rule_for(path: &Path, table: &DeviceTable) -> bool {
+    match fs::read_to_string(path) {
+        Ok(content) => mentions_device(&content.to_lowercase(), table),
+        Err(_) => false,
+    }
+}
+
+fn mentions_device(rule: &str, table: &DeviceTable) -> bool {
+    rule.lines().any(|line| {
+        table.ids().iter().any(|id| {
+            line.contains(&format!("\"{:04x}\"", id.vendor_id)) && line.contains(&format!("\"{:04x}\"", id.product_id))
+        })
+    })
+}
+
+fn check_drivers(table: &DeviceTable, usb: &Option<Vec<devices::UsbDevice>>) -> Check {
+    let usb = match *usb {
+        Some(ref usb) if !usb.is_empty() => usb,
+        Some(_) => return Check::skipped("driver", "No foot switch is connected."),
+        None => return Check::skipped("driver", "The USB bus could not be read."),
+    };
+
+    let mut drivers = Vec::new();
+
+    for device in usb.iter() {
+        let number = table.find(device.vendor_id, device.product_id).map(|id| id.interface).unwrap_or(1);
+
+        let interface: PathBuf = match device.interface(number) {
+            Some(interface) => interface,
+            None => return Check::failed("driver", format!("{:04x}:{:04x} has no interface {}.", device.vendor_id, device.product_id, number),
+                "The device may use another interface for its configuration. Check 'lsusb -v' and use --add-device \
+                 vendorId:productId:interface.".to_string()),
+        };
+
+        match devices::interface_driver(&interface) {
+            Some(ref driver) if driver == "usbhid" => drivers.push(format!("usbhid claimed interface {}", number)),
+            Some(driver) => return Check::warning("driver", format!("Interface {} is claimed by {} instead of usbhid.", number, driver),
+                format!("Unbind it with 'echo {} | sudo tee /sys/bus/usb/drivers/{}/unbind' and reconnect the foot switch.",
+                        interface.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(), driver)),
+            None => return Check::failed("driver", format!("No kernel driver claimed interface {}, so there is no hidraw node.", number),
+                "Load the driver with 'sudo modprobe usbhid' and reconnect the foot switch.".to_string()),
+        }
+    }
+
+    Check::ok("driver", format!("{}.", drivers.join(", ")))
+}
+
+fn check_query(table: &DeviceTable, selector: &DeviceSelector) -> Check {
+    match pedal_operations::open_device(selector, table) {
+        Ok(dev) => query(&Pedals::with_transport(dev)),
+        Err(e) => Check::failed("query", format!("Could not open the foot switch: {}", e),
+            "Select a device with --device, --serial, or --index if several are connected. Otherwise, reconnect the foot switch.".to_string()),
+    }
+}
+
+/// Checks if pedal 0 answers a query with a valid configuration
+fn query<T: Transport>(pedals: &Pedals<T>) -> Check {
+    let response = match pedals.read_raw(0) {
+        Ok(response) => response,
+        Err(FootswitchError::Protocol(e)) => return unexpected_response(format!("Pedal 0 answered with an unexpected response. {}", e)),
+        Err(e) => return Check::failed("query", format!("Querying pedal 0 failed: {}", e),
+            "Reconnect the foot switch. If this keeps happening, record the communication with --trace and report it.".to_string()),
+    };
+
+    match PedalConfig::decode(&response) {
+        Ok(_) => Check::ok("query", format!("Pedal 0 answered: {}.", protocol::Describer::new().incoming(&response[..protocol::PACKET_SIZE]))),
+        Err(_) => unexpected_response(format!("Pedal 0 answered with an unexpected response ({}).", protocol::to_hex(&response))),
+    }
+}
+
+fn unexpected_response(message: String) -> Check {
+    Check::failed("query", message,
+        "Reconnect the foot switch. If the answer stays the same, the device may speak another protocol; \
+         record the communication with --trace and report it.".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::Emulator;
+    use transport::MockTransport;
+
+    #[test]
+    fn finds_rules_for_supported_devices() {
+        let table = DeviceTable::default();
+
+        assert!(mentions_device("subsystem==\"hidraw\", attrs{idvendor}==\"0c45\", attrs{idproduct}==\"7403\", tag+=\"uaccess\"", &table));
+        assert!(!mentions_device("subsystem==\"hidraw\", attrs{idvendor}==\"0c45\", attrs{idproduct}==\"6001\", tag+=\"uaccess\"", &table));
+    }
+
+    #[test]
+    fn healthy_device_answers_query() {
+        let emulator = Emulator::new();
+        emulator.set_pedal_data(0, &[0x08, 0x01, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00]);
+
+        let check = query(&Pedals::with_transport(&emulator));
+
+        assert_eq!(check.status, Status::Ok);
+        assert_eq!(check.fix, None);
+    }
+
+    #[test]
+    fn unexpected_answer_fails_query() {
+        let dev = MockTransport::new();
+        dev.push_reply(&[0x08, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        assert_eq!(query(&Pedals::with_transport(&dev)).status, Status::Failed);
+    }
+}
@@ -0,0 +1,351 @@
+//! Software model of the PCSensor configuration firmware
+//!
+//! The `Emulator` accepts exactly the packets that are described in `protocol`
+//! and answers queries the way a real foot switch does. It implements
+//! `Transport`, so it can be used everywhere a real device can be used.
+//!
+//! Optionally, the memory of the pedals is persisted to a state file. This file
+//! contains one line per pedal with the stored data bytes in hexadecimal notation.
+
+use std::io;
+use std::fs;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use protocol;
+use transport::Transport;
+
+enum Mode {
+    /// No start packet was received yet, only queries are accepted
+    Idle,
+
+    /// A start packet was received, headers are accepted
+    Programming,
+
+    /// A header was received, `remaining` data bytes are expected for `ped`
+    Receiving { ped: usize, remaining: usize },
+}
+
+struct State {
+    pedals: Vec<Vec<u8>>,
+    mode: Mode,
+    responses: VecDeque<Vec<u8>>,
+    path: Option<PathBuf>,
+}
+
+pub struct Emulator {
+    state: RefCell<State>,
+}
+
+impl Default for Emulator {
+    fn default() -> Emulator {
+        Emulator::new()
+    }
+}
+
+impl Emulator {
+    /// Create an emulator with three unconfigured pedals
+    pub fn new() -> Emulator {
+        Emulator {
+            state: RefCell::new(State {
+                pedals: vec![protocol::unconfigured_data().to_vec(); protocol::NUM_PEDALS],
+                mode: Mode::Idle,
+                responses: VecDeque::new(),
+                path: None,
+            })
+        }
+    }
+
+    /// Create an emulator that is backed by a state file. If the file does not
+    /// exist yet, all pedals start unconfigured and the file is created on the
+    /// first write.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Emulator> {
+        let path = path.as_ref();
+        let emulator = Emulator::new();
+
+        if path.exists() {
+            let content = fs::read_to_string(path)?;
+            emulator.state.borrow_mut().pedals = parse_state(&content)?;
+        }
+
+        emulator.state.borrow_mut().path = Some(path.to_path_buf());
+
+        Ok(emulator)
+    }
+
+    /// Returns the bytes that are currently stored for pedal `ped`
+    pub fn pedal_data(&self, ped: usize) -> Vec<u8> {
+        self.state.borrow().pedals[ped].clone()
+    }
+
+    /// Overwrite the bytes that are stored for pedal `ped`
+    pub fn set_pedal_data(&self, ped: usize, data: &[u8]) {
+        self.state.borrow_mut().pedals[ped] = data.to_vec();
+    }
+
+    /// Write the memory of all pedals to the state file, if there is one
+    pub fn save(&self) -> io::Result<()> {
+        let state = self.state.borrow();
+
+        if let Some(ref path) = state.path {
+            fs::write(path, format_state(&state.pedals))?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_packet(&self, packet: &[u8]) -> io::Result<()> {
+        let mut state = self.state.borrow_mut();
+
+        // Data packets are only recognized by the fact that a header preceded them
+        if let Mode::Receiving { ped, remaining } = state.mode {
+            if packet.len() > remaining {
+                return Err(invalid_data(format!("Received {} data bytes for pedal {}, but only {} were announced", packet.len(), ped, remaining)));
+            }
+
+            state.pedals[ped].extend_from_slice(packet);
+
+            let remaining = remaining - packet.len();
+
+            if remaining > 0 {
+                state.mode = Mode::Receiving { ped, remaining };
+                return Ok(());
+            }
+
+            state.mode = Mode::Programming;
+            drop(state);
+
+            return self.save();
+        }
+
+        if packet.len() != protocol::PACKET_SIZE || packet[0] != protocol::REPORT_ID {
+            return Err(invalid_data(format!("Unexpected packet [{}]", protocol::to_hex(packet))));
+        }
+
+        match packet[1] {
+            protocol::START => {
+                state.mode = Mode::Programming;
+            },
+            protocol::HEADER => {
+                if let Mode::Idle = state.mode {
+                    return Err(invalid_data("Received a header before the start packet".to_string()));
+                }
+
+                let ped = pedal_index(packet[3])?;
+                let len = packet[2] as usize;
+
+                if !(2..=protocol::MAX_DATA_LEN).contains(&len) {
+                    return Err(invalid_data(format!("Invalid data length {} for pedal {}", len, ped)));
+                }
+
+                state.pedals[ped].clear();
+                state.mode = Mode::Receiving { ped, remaining: len };
+            },
+            protocol::QUERY => {
+                let ped = pedal_index(packet[3])?;
+                let mut data = state.pedals[ped].clone();
+
+                // Answers always consist of complete packets
+                let padded_len = data.len().div_ceil(protocol::PACKET_SIZE) * protocol::PACKET_SIZE;
+                data.resize(padded_len, 0);
+
+                for chunk in data.chunks(protocol::PACKET_SIZE) {
+                    state.responses.push_back(chunk.to_vec());
+                }
+            },
+            _ => {
+                return Err(invalid_data(format!("Unknown command {:#04x}", packet[1])));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Transport for Emulator {
+    fn write(&self, data: &[u8]) -> io::Result<usize> {
+        self.handle_packet(data)?;
+
+        Ok(data.len())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.state.borrow_mut().responses.pop_front() {
+            Some(response) => {
+                let len = response.len().min(buf.len());
+                buf[..len].copy_from_slice(&response[..len]);
+
+                Ok(len)
+            },
+            None => Err(io::Error::new(io::ErrorKind::TimedOut, "The emulator has no pending response. Send a query first.")),
+        }
+    }
+}
+
+fn pedal_index(value: u8) -> io::Result<usize> {
+    if value == 0 || value as usize > protocol::NUM_PEDALS {
+        return Err(invalid_data(format!("Invalid pedal number {}", value)));
+    }
+
+    Ok(value as usize - 1)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_state(content: &str) -> io::Result<Vec<Vec<u8>>> {
+    let mut pedals = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match protocol::from_hex(line) {
+            Some(ref data) if !data.is_empty() && data.len() <= protocol::MAX_DATA_LEN => pedals.push(data.clone()),
+            _ => return Err(invalid_data(format!("Invalid line in emulator state file: '{}'", line))),
+        }
+    }
+
+    if pedals.len() != protocol::NUM_PEDALS {
+        return Err(invalid_data(format!("Emulator state file must contain {} pedals, found {}", protocol::NUM_PEDALS, pedals.len())));
+    }
+
+    Ok(pedals)
+}
+
+fn format_state(pedals: &[Vec<u8>]) -> String {
+    let mut content = String::from("# footswitch-rs emulator state, one line per pedal\n");
+
+    for data in pedals.iter() {
+        content.push_str(&protocol::to_hex(data));
+        content.push('\n');
+    }
+
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use pedal_config::PedalConfig;
+    use pedal_operations::Pedals;
+
+    #[test]
+    fn write_then_query_every_type() {
+        let emulator = Emulator::new();
+        let mut pedals = Pedals::with_transport(&emulator);
+
+        let configs = vec![
+            PedalConfig::Key { modifiers: 0x05, key: 0x4c },
+            PedalConfig::Mouse { buttons: 0x01, x: -10, y: 10, w: 0 },
+            PedalConfig::MouseKey { modifiers: 0x02, key: 0x05, buttons: 0x02, x: 0, y: 0, w: -1 },
+            PedalConfig::String { keys: vec![0x0b, 0x08, 0x0f] },
+            PedalConfig::String { keys: (0x04..0x28).collect() },
+            PedalConfig::Unconfigured,
+        ];
+
+        for (i, config) in configs.into_iter().enumerate() {
+            let ped = i % protocol::NUM_PEDALS;
+
+            pedals.set_config(ped, config.clone()).unwrap();
+            pedals.write_verified(&[ped], 0).unwrap();
+
+            assert_eq!(emulator.pedal_data(ped), config.encode());
+            assert_eq!(pedals.read_config(ped as u8).unwrap(), config);
+        }
+    }
+
+    #[test]
+    fn answers_queries_with_complete_packets() {
+        let emulator = Emulator::new();
+        let string = [0x0d, 0x04, 0x0b, 0x08, 0x0f, 0x0f, 0x12, 0x2c, 0x1a, 0x12, 0x15, 0x0f, 0x07];
+        emulator.set_pedal_data(1, &string);
+
+        let mut buf = [0u8; 8];
+
+        // Nothing was asked yet
+        assert_eq!(emulator.read(&mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        emulator.write(&protocol::query_packet(1)).unwrap();
+
+        assert_eq!(emulator.read(&mut buf).unwrap(), 8);
+        assert_eq!(buf.to_vec(), string[..8].to_vec());
+        assert_eq!(emulator.read(&mut buf).unwrap(), 8);
+        assert_eq!(buf.to_vec(), vec![0x1a, 0x12, 0x15, 0x0f, 0x07, 0x00, 0x00, 0x00]);
+        assert!(emulator.read(&mut buf).is_err());
+
+        emulator.write(&protocol::query_packet(2)).unwrap();
+        emulator.read(&mut buf).unwrap();
+        assert_eq!(buf, protocol::unconfigured_data());
+    }
+
+    #[test]
+    fn rejects_malformed_sessions() {
+        let emulator = Emulator::new();
+
+        // Header before the start packet
+        assert!(emulator.write(&protocol::header_packet(0, 8)).is_err());
+
+        // Wrong report ID, unknown command, and short packets
+        assert!(emulator.write(&[0x02, 0x80, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00]).is_err());
+        assert!(emulator.write(&[0x01, 0x83, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00]).is_err());
+        assert!(emulator.write(&[0x01, 0x80]).is_err());
+
+        // Invalid pedals
+        assert!(emulator.write(&[0x01, 0x82, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00]).is_err());
+        assert!(emulator.write(&protocol::query_packet(3)).is_err());
+
+        emulator.write(&protocol::start_packet()).unwrap();
+
+        // Invalid data lengths
+        assert!(emulator.write(&protocol::header_packet(0, 1)).is_err());
+        assert!(emulator.write(&protocol::header_packet(0, protocol::MAX_DATA_LEN as u8 + 1)).is_err());
+
+        // More data than announced
+        emulator.write(&protocol::header_packet(0, 3)).unwrap();
+        assert!(emulator.write(&[0x03, 0x04, 0x04, 0x05]).is_err());
+    }
+
+    #[test]
+    fn persists_state_across_instances() {
+        let path = env::temp_dir().join(format!("footswitch-rs-emulator-{}.txt", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let key = [0x08, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00];
+        let string = [0x0a, 0x04, 0x0b, 0x08, 0x0f, 0x0f, 0x12, 0x2c, 0x1a, 0x12];
+
+        {
+            let emulator = Emulator::open(&path).unwrap();
+
+            // The file is only created by the first write
+            assert!(!path.exists());
+
+            let mut pedals = Pedals::with_transport(&emulator);
+            pedals.set_raw(0, &key).unwrap();
+            pedals.set_raw(2, &string).unwrap();
+            pedals.write_pedals().unwrap();
+        }
+
+        let emulator = Emulator::open(&path).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(emulator.pedal_data(0), key.to_vec());
+        assert_eq!(emulator.pedal_data(1), protocol::unconfigured_data().to_vec());
+        assert_eq!(emulator.pedal_data(2), string.to_vec());
+        assert_eq!(parse_state(&content).unwrap(), vec![key.to_vec(), protocol::unconfigured_data().to_vec(), string.to_vec()]);
+    }
+
+    #[test]
+    fn rejects_invalid_state_files() {
+        assert!(parse_state("08 01 00 04 00 00 00 00\n").is_err());
+        assert!(parse_state("08 01 00 04 00 00 00 00\nzz\n08 00 00 00 00 00 00 00\n").is_err());
+        assert!(parse_state("# comment\n\n08 00\n02 04\n08 00 00 00 00 00 00 00\n").is_ok());
+    }
+}
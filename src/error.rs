@@ -0,0 +1,248 @@
+//! Errors that can occur while talking to a foot switch
+
+use std::io;
+use std::fmt;
+use std::error;
+use std::result;
+use protocol;
+use pedal_config::PedalConfig;
+use backup::Backup;
+
+#[derive(Debug)]
+pub enum FootswitchError {
+    /// No (matching) device could be found or opened
+    DeviceNotFound(String),
+
+    /// More than one device matches, and none was selected. Contains a
+    /// description of every candidate.
+    MultipleDevices(Vec<String>),
+
+    /// The current user is not allowed to access the device
+    PermissionDenied(String),
+
+    /// Communication with the device (or a file) failed
+    Io(io::Error),
+
+    /// Unknown key, modifier, or mouse button
+    InvalidKey(String),
+
+    /// The requested combination of key, mouse, and string is not possible
+    InvalidCombination,
+
+    /// The string does not fit in the memory of a pedal
+    StringTooLong { len: usize, max: usize },
+
+    /// Pedal number outside of [0, 2]
+    InvalidPedal(usize),
+
+    /// The device answered something that does not conform to the protocol
+    Protocol(String),
+
+    /// A profile could not be parsed or contains contradicting settings
+    InvalidProfile(String),
+
+    /// A backup could not be parsed
+    InvalidBackup(String),
+
+    /// The device did not store what was written to it
+    VerificationFailed(Vec<Mismatch>),
+
+    /// Writing to the device failed, and the pedals that were written were
+    /// restored from a snapshot that was taken before
+    WriteFailed {
+        /// Error that made writing fail
+        cause: Box<FootswitchError>,
+
+        /// Pedals that were written
+        pedals: Vec<usize>,
+
+        /// Outcome of restoring the snapshot
+        rollback: Rollback,
+
+        /// Memory of all pedals before writing
+        snapshot: Backup,
+    },
+}
+
+/// Outcome of restoring a snapshot after writing failed
+#[derive(Debug)]
+pub enum Rollback {
+    /// The snapshot was written and verified. The device is in the same state as before.
+    Restored,
+
+    /// The snapshot could not be restored either. The device may be partially updated.
+    Failed(Box<FootswitchError>),
+}
+
+/// Difference between the data that was written to a pedal and the data that
+/// was read back
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub pedal: usize,
+    pub expected: Vec<u8>,
+    pub actual: Vec<u8>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let offset = self.expected.iter().zip(self.actual.iter()).position(|(e, a)| e != a)
+            .unwrap_or_else(|| self.expected.len().min(self.actual.len()));
+
+        match (self.expected.get(offset), self.actual.get(offset)) {
+            (Some(e), Some(a)) => write!(f, "pedal {}: byte {} differs (expected {:02x}, received {:02x})", self.pedal, offset, e, a)?,
+            _ => write!(f, "pedal {}: expected {} bytes, received {}", self.pedal, self.expected.len(), self.actual.len())?,
+        }
+
+        write!(f, "\n        expected: {} ({})", protocol::to_hex(&self.expected), describe(&self.expected))?;
+        write!(f, "\n        received: {} ({})", protocol::to_hex(&self.actual), describe(&self.actual))
+    }
+}
+
+fn describe(data: &[u8]) -> String {
+    match PedalConfig::decode(data) {
+        Ok(config) => config.to_string(),
+        Err(_) => "not decodable".to_string(),
+    }
+}
+
+pub type Result<T> = result::Result<T, FootswitchError>;
+
+/// Exit code for invalid usage of the command line interface
+pub const EXIT_USAGE: i32 = 1;
+pub const EXIT_DEVICE_NOT_FOUND: i32 = 2;
+pub const EXIT_MULTIPLE_DEVICES: i32 = 3;
+pub const EXIT_PERMISSION_DENIED: i32 = 4;
+pub const EXIT_IO: i32 = 5;
+pub const EXIT_INVALID_KEY: i32 = 6;
+pub const EXIT_INVALID_COMBINATION: i32 = 7;
+pub const EXIT_STRING_TOO_LONG: i32 = 8;
+pub const EXIT_INVALID_PEDAL: i32 = 9;
+pub const EXIT_PROTOCOL: i32 = 10;
+pub const EXIT_INVALID_PROFILE: i32 = 11;
+pub const EXIT_INVALID_BACKUP: i32 = 12;
+
+/// Exit code of `diff` if the device does not match the profile
+pub const EXIT_DIFFERENT: i32 = 13;
+
+pub const EXIT_VERIFICATION_FAILED: i32 = 14;
+
+/// Exit code if a write failed and the previous state could not be restored
+pub const EXIT_ROLLBACK_FAILED: i32 = 15;
+
+/// Exit code of `doctor` if a check failed
+pub const EXIT_PROBLEMS: i32 = 16;
+
+/// All exit codes, besides 0 for success
+pub const EXIT_CODES: &[i32] = &[
+    EXIT_USAGE, EXIT_DEVICE_NOT_FOUND, EXIT_MULTIPLE_DEVICES, EXIT_PERMISSION_DENIED, EXIT_IO, EXIT_INVALID_KEY,
+    EXIT_INVALID_COMBINATION, EXIT_STRING_TOO_LONG, EXIT_INVALID_PEDAL, EXIT_PROTOCOL, EXIT_INVALID_PROFILE,
+    EXIT_INVALID_BACKUP, EXIT_DIFFERENT, EXIT_VERIFICATION_FAILED, EXIT_ROLLBACK_FAILED, EXIT_PROBLEMS,
+];
+
+impl FootswitchError {
+    /// Exit code of the application if this error occurs
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            FootswitchError::DeviceNotFound(_) => EXIT_DEVICE_NOT_FOUND,
+            FootswitchError::MultipleDevices(_) => EXIT_MULTIPLE_DEVICES,
+            FootswitchError::PermissionDenied(_) => EXIT_PERMISSION_DENIED,
+            FootswitchError::Io(_) => EXIT_IO,
+            FootswitchError::InvalidKey(_) => EXIT_INVALID_KEY,
+            FootswitchError::InvalidCombination => EXIT_INVALID_COMBINATION,
+            FootswitchError::StringTooLong { .. } => EXIT_STRING_TOO_LONG,
+            FootswitchError::InvalidPedal(_) => EXIT_INVALID_PEDAL,
+            FootswitchError::Protocol(_) => EXIT_PROTOCOL,
+            FootswitchError::InvalidProfile(_) => EXIT_INVALID_PROFILE,
+            FootswitchError::InvalidBackup(_) => EXIT_INVALID_BACKUP,
+            FootswitchError::VerificationFailed(_) => EXIT_VERIFICATION_FAILED,
+            FootswitchError::WriteFailed { ref cause, rollback: Rollback::Restored, .. } => cause.exit_code(),
+            FootswitchError::WriteFailed { rollback: Rollback::Failed(_), .. } => EXIT_ROLLBACK_FAILED,
+        }
+    }
+}
+
+impl fmt::Display for FootswitchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FootswitchError::DeviceNotFound(ref msg) => write!(f, "{}", msg),
+            FootswitchError::MultipleDevices(ref candidates) => {
+                write!(f, "Found {} matching foot switches. Please select one with --device, --serial, or --index:", candidates.len())?;
+
+                for (i, candidate) in candidates.iter().enumerate() {
+                    write!(f, "\n    {}: {}", i, candidate)?;
+                }
+
+                Ok(())
+            },
+            FootswitchError::PermissionDenied(ref msg) => write!(f, "{}", msg),
+            FootswitchError::Io(ref err) => write!(f, "Communication failed: {}", err),
+            FootswitchError::InvalidKey(ref msg) => write!(f, "{}", msg),
+            FootswitchError::InvalidCombination => {
+                write!(f, "Invalid combination of options! Please see https://git.dennispotter.eu/Dennis/footswitch-rs/wiki")
+            },
+            FootswitchError::StringTooLong { len, max } => {
+                write!(f, "The size of the accumulated string ({}) must be smaller than or equal to {}.", len, max)
+            },
+            FootswitchError::InvalidPedal(ped) => write!(f, "Pedal value {} is larger than 2 and thus not valid!", ped),
+            FootswitchError::Protocol(ref msg) => write!(f, "{}", msg),
+            FootswitchError::InvalidProfile(ref msg) => write!(f, "{}", msg),
+            FootswitchError::InvalidBackup(ref msg) => write!(f, "{}", msg),
+            FootswitchError::VerificationFailed(ref mismatches) => {
+                write!(f, "The device did not store what was written to {} pedal(s):", mismatches.len())?;
+
+                for mismatch in mismatches.iter() {
+                    write!(f, "\n    {}", mismatch)?;
+                }
+
+                Ok(())
+            },
+            FootswitchError::WriteFailed { ref cause, ref pedals, ref rollback, .. } => {
+                let pedals: Vec<String> = pedals.iter().map(|ped| ped.to_string()).collect();
+
+                write!(f, "{}", cause)?;
+
+                match *rollback {
+                    Rollback::Restored => {
+                        write!(f, "\nThe previous state of pedal(s) {} was restored and verified. The device was not changed.", pedals.join(", "))
+                    },
+                    Rollback::Failed(ref err) => {
+                        write!(f, "\nRestoring the previous state of pedal(s) {} failed as well, so the device may be partially updated: {}",
+                               pedals.join(", "), err)
+                    },
+                }
+            },
+        }
+    }
+}
+
+impl error::Error for FootswitchError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            FootswitchError::Io(ref err) => Some(err),
+            FootswitchError::WriteFailed { ref cause, .. } => Some(cause.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FootswitchError {
+    fn from(err: io::Error) -> FootswitchError {
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => FootswitchError::PermissionDenied(err.to_string()),
+            _ => FootswitchError::Io(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_unique() {
+        for (i, code) in EXIT_CODES.iter().enumerate() {
+            assert!(*code != 0, "0 signals success");
+            assert!(!EXIT_CODES[i + 1..].contains(code), "Exit code {} is used twice", code);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Key events from Linux event devices (`/dev/input/event*`)
+//!
+//! `EvdevSource` grabs the event devices of a foot switch exclusively, so the
+//! keys that the pedals send reach only the `Daemon` and not the applications
+//! of the user.
+
+use std::io;
+use std::io::Read;
+use std::fs::File;
+use std::mem;
+use std::ptr;
+use std::time::Duration;
+use std::os::unix::io::AsRawFd;
+use libc;
+use daemon::{KeyEvent, KeySource};
+
+/// EVIOCGRAB, _IOW('E', 0x90, int) in linux/input.h
+const EVIOCGRAB: libc::c_ulong = 0x4004_4590;
+
+const EV_KEY: u16 = 0x01;
+
+/// Value of a key event that repeats a held key
+const KEY_REPEAT: i32 = 2;
+
+pub struct EvdevSource {
+    devices: Vec<File>,
+}
+
+impl EvdevSource {
+    /// Open the event devices and grab them. They are released when the source is dropped.
+    pub fn grab(nodes: &[String]) -> io::Result<EvdevSource> {
+        let mut devices = Vec::new();
+
+        for node in nodes.iter() {
+            let device = File::open(node)?;
+
+            if unsafe { libc::ioctl(device.as_raw_fd(), EVIOCGRAB as _, 1 as libc::c_int) } < 0 {
+                let err = io::Error::last_os_error();
+                return Err(io::Error::new(err.kind(), format!("Could not grab {}: {}", node, err)));
+            }
+
+            devices.push(device);
+        }
+
+        Ok(EvdevSource { devices })
+    }
+
+    /// Index of a device that has events to read
+    fn wait(&self) -> io::Result<usize> {
+        if self.devices.len() == 1 {
+            return Ok(0);
+        }
+
+        let mut fds: Vec<libc::pollfd> = self.devices.iter()
+            .map(|device| libc::pollfd { fd: device.as_raw_fd(), events: libc::POLLIN, revents: 0 })
+            .collect();
+
+        loop {
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                let err = io::Error::last_os_error();
+
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+
+                return Err(err);
+            }
+
+            if let Some(index) = fds.iter().position(|fd| fd.revents != 0) {
+                return Ok(index);
+            }
+        }
+    }
+}
+
+impl KeySource for EvdevSource {
+    fn next_event(&mut self) -> io::Result<KeyEvent> {
+        let mut buf = [0u8; mem::size_of::<libc::input_event>()];
+
+        loop {
+            let index = self.wait()?;
+            self.devices[index].read_exact(&mut buf)?;
+
+            let event: libc::input_event = unsafe { ptr::read_unaligned(buf.as_ptr() as *const libc::input_event) };
+
+            if event.type_ != EV_KEY || event.value == KEY_REPEAT {
+                continue;
+            }
+
+            return Ok(KeyEvent {
+                code: event.code,
+                pressed: event.value != 0,
+                time: Duration::new(event.time.tv_sec as u64, event.time.tv_usec as u32 * 1000),
+            });
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Notifications about devices that are plugged in or removed (Linux only)
+//!
+//! `Monitor` listens on a netlink socket for uevents. By default, it receives
+//! the events that udev broadcasts after it processed a device, i.e., after it
+//! applied rules like the one of `udev_rule`, so that a device can be opened as
+//! soon as it is announced. This does not need libudev.
+
+use std::io;
+use std::mem;
+use std::collections::BTreeMap;
+use libc;
+
+/// Netlink group of the raw events of the kernel
+pub const KERNEL_EVENTS: u32 = 1;
+
+/// Netlink group of the events that udev sends after processing a device
+pub const UDEV_EVENTS: u32 = 2;
+
+/// Events of udev start with this prefix, followed by a header (see libudev-monitor.c)
+const UDEV_PREFIX: &[u8] = b"libudev\0";
+const UDEV_MAGIC: u32 = 0xfeed_cafe;
+
+const BUFFER_SIZE: usize = 8192;
+
+/// A device was added, removed, or changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Uevent {
+    pub action: String,
+    pub properties: BTreeMap<String, String>,
+}
+
+impl Uevent {
+    /// Parse an event of the kernel (`add@/devices/...\0KEY=VALUE\0...`) or of udev
+    pub fn parse(buf: &[u8]) -> Option<Uevent> {
+        let properties = if buf.starts_with(UDEV_PREFIX) {
+            let field = |index: usize| {
+                let offset = UDEV_PREFIX.len() + 4 * index;
+                buf.get(offset..(offset + 4)).map(|bytes| [bytes[0], bytes[1], bytes[2], bytes[3]])
+            };
+
+            // The magic number is big endian, the other fields are in native byte order
+            if u32::from_be_bytes(field(0)?) != UDEV_MAGIC {
+                return None;
+            }
+
+            let offset = u32::from_ne_bytes(field(2)?) as usize;
+            let len = u32::from_ne_bytes(field(3)?) as usize;
+
+            buf.get(offset..(offset + len))?
+        }
+        else {
+            // Skip the summary in the first line
+            let start = buf.iter().position(|b| *b == 0)? + 1;
+
+            &buf[start..]
+        };
+
+        let properties: BTreeMap<String, String> = properties.split(|b| *b == 0)
+            .filter_map(|line| {
+                let line = String::from_utf8_lossy(line);
+                let mut parts = line.splitn(2, '=');
+
+                Some((parts.next()?.to_string(), parts.next()?.to_string()))
+            })
+            .collect();
+
+        Some(Uevent {
+            action: properties.get("ACTION")?.clone(),
+            properties,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(|value| value.as_str())
+    }
+
+    pub fn subsystem(&self) -> Option<&str> {
+        self.get("SUBSYSTEM")
+    }
+
+    /// Vendor and product ID of a USB device or interface (`PRODUCT=c45/7403/1`)
+    pub fn usb_id(&self) -> Option<(u16, u16)> {
+        let mut parts = self.get("PRODUCT")?.split('/');
+
+        let vendor_id = u16::from_str_radix(parts.next()?, 16).ok()?;
+        let product_id = u16::from_str_radix(parts.next()?, 16).ok()?;
+
+        Some((vendor_id, product_id))
+    }
+}
+
+/// Netlink socket that receives uevents
+pub struct Monitor {
+    fd: libc::c_int,
+}
+
+impl Monitor {
+    /// Listen to the events of a group, i.e., `UDEV_EVENTS` or `KERNEL_EVENTS`
+    pub fn new(group: u32) -> io::Result<Monitor> {
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, libc::NETLINK_KOBJECT_UEVENT) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Close the socket if binding fails
+        let monitor = Monitor { fd };
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = group;
+
+        let res = unsafe {
+            libc::bind(fd, &addr as *const libc::sockaddr_nl as *const libc::sockaddr, mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t)
+        };
+
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(monitor)
+    }
+
+    /// Wait for the next event. Messages that are not uevents are skipped.
+    pub fn next_event(&self) -> io::Result<Uevent> {
+        let mut buf = [0u8; BUFFER_SIZE];
+
+        loop {
+            let len = unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+
+            if len < 0 {
+                let err = io::Error::last_os_error();
+
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+
+                return Err(err);
+            }
+
+            if let Some(event) = Uevent::parse(&buf[..(len as usize)]) {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROPERTIES: &[u8] = b"ACTION=add\0DEVPATH=/devices/pci0000:00/usb1/1-2\0SUBSYSTEM=usb\0DEVTYPE=usb_device\0PRODUCT=c45/7403/1\0";
+
+    #[test]
+    fn parses_kernel_events() {
+        let mut buf = b"add@/devices/pci0000:00/usb1/1-2\0".to_vec();
+        buf.extend_from_slice(PROPERTIES);
+
+        let event = Uevent::parse(&buf).unwrap();
+
+        assert_eq!(event.action, "add");
+        assert_eq!(event.subsystem(), Some("usb"));
+        assert_eq!(event.usb_id(), Some((0x0c45, 0x7403)));
+    }
+
+    #[test]
+    fn parses_udev_events() {
+        let header_size = UDEV_PREFIX.len() + 4 * 4;
+
+        let mut buf = UDEV_PREFIX.to_vec();
+        buf.extend_from_slice(&UDEV_MAGIC.to_be_bytes());
+        buf.extend_from_slice(&(header_size as u32).to_ne_bytes());
+        buf.extend_from_slice(&(header_size as u32).to_ne_bytes());
+        buf.extend_from_slice(&(PROPERTIES.len() as u32).to_ne_bytes());
+        buf.extend_from_slice(PROPERTIES);
+
+        let event = Uevent::parse(&buf).unwrap();
+
+        assert_eq!(event.action, "add");
+        assert_eq!(event.get("DEVTYPE"), Some("usb_device"));
+        assert_eq!(event.usb_id(), Some((0x0c45, 0x7403)));
+    }
+}
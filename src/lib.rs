@@ -0,0 +1,53 @@
+//! Footswitch-RS
+//!
+//! `footswitch-rs` enables you to manage PCSensor foot switches. Following
+//! vnedorId:productId combinations are supported:
+//!
+//!  * 0c45:7403
+//!  * 0c45:7404
+//!  * 413d:2107
+//!
+//! Besides the command line application, this crate can be used as a library:
+//!
+//!  * `devices` discovers connected foot switches,
+//!  * `Pedals` reads the configuration of a foot switch and writes it back, and
+//!  * `transport` and `emulator` make it possible to do so without hardware.
+
+extern crate hidapi;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
+extern crate libc;
+
+pub mod error;
+pub mod key_operations;
+pub mod pedal_config;
+pub mod pedal_operations;
+pub mod transport;
+pub mod trace;
+pub mod session;
+pub mod protocol;
+pub mod emulator;
+#[cfg(target_os = "linux")]
+pub mod uhid;
+#[cfg(target_os = "linux")]
+pub mod hotplug;
+pub mod devices;
+pub mod udev_rule;
+pub mod doctor;
+pub mod listen;
+pub mod daemon;
+#[cfg(target_os = "linux")]
+pub mod evdev;
+pub mod config;
+pub mod profile;
+pub mod backup;
+
+pub use error::{FootswitchError, Result};
+pub use pedal_config::PedalConfig;
+pub use profile::Profile;
+pub use pedal_operations::{Pedals, DeviceSelector, open_device};
+pub use devices::{DeviceId, DeviceInfo, DeviceTable, Protocol, list_devices};
+pub use transport::Transport;
@@ -0,0 +1,404 @@
+//! Monitoring of the pedals while they are pressed
+//!
+//! When a pedal is pressed, the foot switch sends what the pedal is configured
+//! to (a key, a mouse button, ...) as a regular HID report on its keyboard
+//! interface (0). The reports do not say which pedal was pressed, so
+//! `Listener` compares them with the configuration of the pedals. Every report
+//! contains everything that is currently pressed, so a pedal is released by
+//! the first report that does not match it anymore. That way, pedals that are
+//! pressed at the same time are released one by one.
+//!
+//! String pedals type one key after another, so they produce a press and a
+//! release for every key.
+
+use std::fmt;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+use transport::Transport;
+use pedal_config::PedalConfig;
+use key_operations::Modifier;
+use protocol;
+use error::Result;
+
+/// Size of the largest report that is read
+const REPORT_SIZE: usize = 64;
+
+/// Report IDs of devices that send keyboard and mouse reports on the same interface
+const KEYBOARD_REPORT_ID: u8 = 1;
+const MOUSE_REPORT_ID: u8 = 2;
+
+/// Contents of a HID report of the keyboard interface
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Report {
+    /// Boot keyboard report: modifiers, a reserved byte, and up to six keys
+    Keyboard { modifiers: u8, keys: Vec<u8> },
+
+    /// Mouse report: buttons and movement
+    Mouse { buttons: u8, x: i8, y: i8, w: i8 },
+
+    /// Report that is not understood
+    Unknown,
+}
+
+impl Report {
+    pub fn decode(data: &[u8]) -> Report {
+        match (data.len(), data.first()) {
+            (8, _) => Report::keyboard(data),
+            (9, Some(&KEYBOARD_REPORT_ID)) => Report::keyboard(&data[1..]),
+            (4, _) => Report::mouse(data),
+            (5, Some(&MOUSE_REPORT_ID)) => Report::mouse(&data[1..]),
+            _ => Report::Unknown,
+        }
+    }
+
+    fn keyboard(data: &[u8]) -> Report {
+        Report::Keyboard {
+            modifiers: data[0],
+            keys: data[2..].iter().cloned().filter(|key| *key != 0).collect(),
+        }
+    }
+
+    fn mouse(data: &[u8]) -> Report {
+        Report::Mouse { buttons: data[0], x: data[1] as i8, y: data[2] as i8, w: data[3] as i8 }
+    }
+
+    /// Checks if nothing is pressed anymore
+    pub fn is_release(&self) -> bool {
+        match *self {
+            Report::Keyboard { modifiers, ref keys } => modifiers == 0 && keys.is_empty(),
+            Report::Mouse { buttons, x, y, w } => buttons == 0 && x == 0 && y == 0 && w == 0,
+            Report::Unknown => false,
+        }
+    }
+
+    /// Checks if a pedal with the given configuration sends this report
+    fn matches(&self, config: &PedalConfig) -> bool {
+        match (self, config) {
+            (&Report::Keyboard { .. }, _) => !self.matched_keys(config).is_empty(),
+
+            (&Report::Mouse { buttons, x, y, w }, &PedalConfig::Mouse { buttons: b, x: cx, y: cy, w: cw }) |
+            (&Report::Mouse { buttons, x, y, w }, &PedalConfig::MouseKey { buttons: b, x: cx, y: cy, w: cw, .. }) => {
+                (buttons != 0 && buttons & b != 0) || (buttons == 0 && (x, y, w) == (cx, cy, cw))
+            },
+
+            _ => false,
+        }
+    }
+
+    /// Keys (with modifiers) that a pedal with the given configuration sends and
+    /// that this report contains. Several pedals may be pressed at the same
+    /// time, so the report may contain more.
+    fn matched_keys(&self, config: &PedalConfig) -> Vec<SentKey> {
+        let (modifiers, keys) = match *self {
+            Report::Keyboard { modifiers, ref keys } => (modifiers, keys),
+            _ => return Vec::new(),
+        };
+
+        let sent = match *config {
+            PedalConfig::Key { modifiers, key } | PedalConfig::MouseKey { modifiers, key, .. } => vec![(modifiers, Some(key))],
+            PedalConfig::String { keys: ref string } => string.iter().map(|c| typed_key(*c)).collect(),
+            _ => Vec::new(),
+        };
+
+        sent.into_iter().filter(|&(m, key)| match key {
+            Some(key) => modifiers & m == m && keys.contains(&key),
+            None => m != 0 && modifiers & m == m,
+        }).collect()
+    }
+}
+
+/// Modifiers and key that a pedal sends. Modifier keys are only sent as modifiers, so they have no key.
+type SentKey = (u8, Option<u8>);
+
+/// Modifiers and key that a string pedal sends to type the given byte of the string
+fn typed_key(c: u8) -> SentKey {
+    match c {
+        // Shifted characters, e.g., `A` is `a` with the highest bit set
+        0x84..=0xb8 => (Modifier::Shift as u8, Some(c & 0x7f)),
+
+        // Modifier keys
+        0xe0..=0xe7 => (1 << (c - 0xe0), None),
+
+        _ => (0, Some(c)),
+    }
+}
+
+impl fmt::Display for Report {
+    /// Prints the report like `read` prints the configuration of a pedal
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Report::Keyboard { modifiers, ref keys } if !keys.is_empty() => {
+                let keys: Vec<String> = keys.iter().map(|key| PedalConfig::Key { modifiers, key: *key }.to_string()).collect();
+
+                write!(f, "{}", keys.join(" "))
+            },
+            Report::Keyboard { .. } => write!(f, "nothing pressed"),
+            Report::Mouse { buttons, x, y, w } => write!(f, "{}", PedalConfig::Mouse { buttons, x, y, w }),
+            Report::Unknown => write!(f, "unknown report"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Press,
+    Release,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Action::Press => write!(f, "pressed"),
+            Action::Release => write!(f, "released"),
+        }
+    }
+}
+
+/// A pedal was pressed or released
+#[derive(Serialize, Debug, Clone)]
+pub struct PedalEvent {
+    /// Seconds since the UNIX epoch
+    pub timestamp: f64,
+
+    pub action: Action,
+
+    /// Number of the pedal, if it could be determined
+    pub pedal: Option<usize>,
+
+    /// Decoded report, see `Display` of `Report`
+    pub description: String,
+
+    pub report: Report,
+
+    /// Bytes of the report in hexadecimal
+    pub raw: String,
+}
+
+/// Reads the reports of the keyboard interface and turns them into events
+pub struct Listener<T: Transport> {
+    dev: T,
+    configs: Vec<PedalConfig>,
+
+    /// Pedals that are pressed. `None` stands for a report that matches no pedal.
+    pressed: Vec<Option<usize>>,
+
+    /// Events of the last report that were not returned yet
+    pending: VecDeque<PedalEvent>,
+}
+
+impl<T: Transport> Listener<T> {
+    /// Listen on the keyboard interface of a foot switch whose pedals have the given configuration
+    pub fn new(dev: T, configs: Vec<PedalConfig>) -> Listener<T> {
+        Listener { dev, configs, pressed: Vec::new(), pending: VecDeque::new() }
+    }
+
+    /// Wait for the next press or release
+    pub fn next_event(&mut self) -> Result<PedalEvent> {
+        let mut buf = [0u8; REPORT_SIZE];
+
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+
+            let len = self.dev.read(&mut buf[..])?;
+            let report = Report::decode(&buf[..len]);
+
+            let pressed: Vec<Option<usize>> = if report.is_release() {
+                Vec::new()
+            }
+            else {
+                match self.matching_pedals(&report) {
+                    ref pedals if pedals.is_empty() => vec![None],
+                    pedals => pedals.into_iter().map(Some).collect(),
+                }
+            };
+
+            // A release without a press (e.g., because the listener started while a pedal was pressed) yields no event
+            let events: Vec<(Action, Option<usize>)> = self.pressed.iter()
+                .filter(|pedal| !pressed.contains(pedal))
+                .map(|pedal| (Action::Release, *pedal))
+                .chain(pressed.iter().filter(|pedal| !self.pressed.contains(pedal)).map(|pedal| (Action::Press, *pedal)))
+                .collect();
+
+            self.pressed = pressed;
+
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+            for (action, pedal) in events {
+                self.pending.push_back(PedalEvent {
+                    timestamp: now.as_secs() as f64 + f64::from(now.subsec_micros()) / 1e6,
+                    action,
+                    pedal,
+                    description: report.to_string(),
+                    report: report.clone(),
+                    raw: protocol::to_hex(&buf[..len]),
+                });
+            }
+        }
+    }
+
+    /// Pedals that send (a part of) a report. Of pedals with the same configuration,
+    /// only the first one matches, and a key only matches the pedals that send it
+    /// with the most modifiers, e.g., `shift+a` does not match a pedal that sends `a`.
+    fn matching_pedals(&self, report: &Report) -> Vec<usize> {
+        let candidates: Vec<(usize, Vec<SentKey>)> = (0..self.configs.len())
+            .filter(|ped| report.matches(&self.configs[*ped]) && !self.configs[..*ped].contains(&self.configs[*ped]))
+            .map(|ped| (ped, report.matched_keys(&self.configs[ped])))
+            .collect();
+
+        let all_keys: Vec<SentKey> = candidates.iter().flat_map(|(_, keys)| keys.iter().cloned()).collect();
+
+        // Keys that another pedal sends with more modifiers
+        let is_covered = |&(modifiers, key): &SentKey| all_keys.iter().any(|&(m, k)| {
+            m != modifiers && m & modifiers == modifiers && (k == key || key.is_none())
+        });
+
+        candidates.iter()
+            .filter(|(_, keys)| keys.is_empty() || keys.iter().any(|key| !is_covered(key)))
+            .map(|(ped, _)| *ped)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transport::MockTransport;
+
+    #[test]
+    fn finds_pressed_pedals() {
+        let dev = MockTransport::new();
+        let configs = vec![
+            PedalConfig::Key { modifiers: 0x01, key: 0x04 },
+            PedalConfig::Mouse { buttons: 0x01, x: 0, y: 0, w: 0 },
+            PedalConfig::String { keys: vec![0x0b, 0x0c] },
+        ];
+
+        dev.push_reply(&[0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        dev.push_reply(&[0x00; 8]);
+        dev.push_reply(&[0x01, 0x00, 0x00, 0x00]);
+        dev.push_reply(&[0x00; 4]);
+        dev.push_reply(&[0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        let mut listener = Listener::new(&dev, configs);
+
+        let events: Vec<(Action, Option<usize>)> = (0..5).map(|_| {
+            let event = listener.next_event().unwrap();
+            (event.action, event.pedal)
+        }).collect();
+
+        assert_eq!(events, vec![
+            (Action::Press, Some(0)),
+            (Action::Release, Some(0)),
+            (Action::Press, Some(1)),
+            (Action::Release, Some(1)),
+            (Action::Press, Some(2)),
+        ]);
+    }
+
+    #[test]
+    fn ignores_release_without_press() {
+        let dev = MockTransport::new();
+
+        dev.push_reply(&[0x00; 8]);
+        dev.push_reply(&[0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        let event = Listener::new(&dev, vec![PedalConfig::Unconfigured; 3]).next_event().unwrap();
+
+        assert_eq!(event.action, Action::Press);
+        assert_eq!(event.pedal, None);
+        assert_eq!(event.description, "<b>");
+    }
+
+    fn events(listener: &mut Listener<&MockTransport>, count: usize) -> Vec<(Action, Option<usize>)> {
+        (0..count).map(|_| {
+            let event = listener.next_event().unwrap();
+            (event.action, event.pedal)
+        }).collect()
+    }
+
+    #[test]
+    fn releases_overlapping_presses() {
+        let dev = MockTransport::new();
+        let configs = vec![
+            PedalConfig::Key { modifiers: 0x00, key: 0x04 },
+            PedalConfig::Key { modifiers: 0x01, key: 0x05 },
+            PedalConfig::Key { modifiers: 0x00, key: 0x06 },
+        ];
+
+        dev.push_reply(&[0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        dev.push_reply(&[0x01, 0x00, 0x04, 0x05, 0x00, 0x00, 0x00, 0x00]);
+        dev.push_reply(&[0x01, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        dev.push_reply(&[0x00, 0x00, 0x04, 0x06, 0x00, 0x00, 0x00, 0x00]);
+        dev.push_reply(&[0x00; 8]);
+
+        let mut listener = Listener::new(&dev, configs);
+
+        assert_eq!(events(&mut listener, 8), vec![
+            (Action::Press, Some(0)),
+            (Action::Press, Some(1)),
+            (Action::Release, Some(0)),
+            (Action::Release, Some(1)),
+            (Action::Press, Some(0)),
+            (Action::Press, Some(2)),
+            (Action::Release, Some(0)),
+            (Action::Release, Some(2)),
+        ]);
+    }
+
+    #[test]
+    fn prefers_pedals_with_more_modifiers() {
+        let dev = MockTransport::new();
+        let configs = vec![
+            PedalConfig::Key { modifiers: 0x00, key: 0x04 },
+            PedalConfig::Key { modifiers: 0x01, key: 0x04 },
+            PedalConfig::Key { modifiers: 0x00, key: 0x04 },
+        ];
+
+        dev.push_reply(&[0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        dev.push_reply(&[0x00; 8]);
+        dev.push_reply(&[0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        let mut listener = Listener::new(&dev, configs);
+
+        assert_eq!(events(&mut listener, 3), vec![
+            (Action::Press, Some(1)),
+            (Action::Release, Some(1)),
+            (Action::Press, Some(0)),
+        ]);
+    }
+
+    #[test]
+    fn matches_shifted_and_high_string_keys() {
+        let dev = MockTransport::new();
+        let configs = vec![
+            PedalConfig::Key { modifiers: 0x00, key: 0x04 },
+            PedalConfig::Unconfigured,
+            // "A", XF86AudioRaiseVolume, and Control_L
+            PedalConfig::String { keys: vec![0x84, 0x80, 0xe0] },
+        ];
+
+        dev.push_reply(&[0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        dev.push_reply(&[0x00; 8]);
+        dev.push_reply(&[0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        dev.push_reply(&[0x00; 8]);
+        dev.push_reply(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        dev.push_reply(&[0x00; 8]);
+        dev.push_reply(&[0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        let mut listener = Listener::new(&dev, configs);
+
+        assert_eq!(events(&mut listener, 7), vec![
+            (Action::Press, Some(2)),
+            (Action::Release, Some(2)),
+            (Action::Press, Some(2)),
+            (Action::Release, Some(2)),
+            (Action::Press, Some(2)),
+            (Action::Release, Some(2)),
+            (Action::Press, Some(0)),
+        ]);
+    }
+}
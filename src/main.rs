@@ -1,30 +1,89 @@
-//! Footswitch-RS
-//!
-//! `footswitch-rs` enables you to manage PCSensor foot switches. Following
-//! vnedorId:productId combinations are supported:
-//!
-//!  * 0c45:7403
-//!  * 0c45:7404
-//!  * 413d:2107
+//! Command line interface of footswitch-rs
 //!
+//! All communication with the foot switch is implemented in the library. This
+//! binary only parses the command line and presents the results to the user.
 
+extern crate footswitch_rs;
 extern crate structopt;
 extern crate users;
 extern crate colored;
+extern crate serde_json;
 
 #[macro_use]
-pub mod messages;
-pub mod key_operations;
-pub mod pedal_operations;
+mod messages;
 
+use std::env;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::fs::{File, OpenOptions};
 use std::process;
+use std::path::Path;
 use structopt::StructOpt;
 use messages::*;
 use colored::*;
+use footswitch_rs::{config, daemon, devices, doctor, emulator, key_operations, listen, pedal_operations, protocol, udev_rule};
+#[cfg(target_os = "linux")]
+use footswitch_rs::uhid;
+#[cfg(target_os = "linux")]
+use footswitch_rs::hotplug;
+#[cfg(target_os = "linux")]
+use footswitch_rs::evdev;
+#[cfg(target_os = "linux")]
+use std::collections::HashSet;
+use footswitch_rs::transport::{MockTransport, Transport};
+use footswitch_rs::trace::Trace;
+use footswitch_rs::session::{Recorder, Replay};
+use footswitch_rs::error::{FootswitchError, Rollback, Result, EXIT_DIFFERENT, EXIT_PROBLEMS};
+use footswitch_rs::profile;
+use footswitch_rs::profile::Profile;
+use footswitch_rs::PedalConfig;
+use footswitch_rs::backup::Backup;
+
+/// Environment variable that enables the packet trace: `stderr`, `1`, or a file name
+const TRACE_VARIABLE: &str = "FOOTSWITCH_TRACE";
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "rust-footswitch")]
 struct Opt {
+    /// Path of the device to use. Use 'emulator:<state-file>' to use a software emulated foot switch,
+    /// or 'replay:<session-file>' to play a recorded session back
+    #[structopt(long = "device")]
+    device: Option<String>,
+
+    /// Serial number of the device to use
+    #[structopt(long = "serial")]
+    serial: Option<String>,
+
+    /// Index of the device to use, if several foot switches are connected
+    #[structopt(long = "index")]
+    index: Option<usize>,
+
+    /// Print the packets that would be written instead of writing them. No device is opened, unless one is
+    /// selected with --device, --serial, or --index; its current state is then read, but nothing is written.
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+
+    /// Log every packet that is exchanged with the device to stderr
+    #[structopt(long = "trace")]
+    trace: bool,
+
+    /// Log every packet that is exchanged with the device to a file (appends)
+    #[structopt(long = "trace-file")]
+    trace_file: Option<String>,
+
+    /// Record every packet that is exchanged with the device to a session file, which can be replayed with --device replay:<file>
+    #[structopt(long = "record")]
+    record: Option<String>,
+
+    /// Number of times pedals are written again if reading them back shows that they were not stored correctly
+    #[structopt(long = "retries", default_value = "0")]
+    retries: usize,
+
+    /// Add a device to the supported devices: vendorId:productId[:interface[:protocol]]
+    #[structopt(long = "add-device", raw(number_of_values = "1"), parse(try_from_str))]
+    add_device: Vec<devices::DeviceId>,
+
     #[structopt(subcommand)]
     cmd: Option<Command>,
 }
@@ -71,7 +130,155 @@ enum Command {
         /// Specify specific pedals. Possible values: [0 | 1 | 2]
         #[structopt(short = "p", long = "pedal")]
         pedals: Vec<u8>,
-    }
+    },
+
+    /// Save the configuration of all pedals to a profile (TOML, or JSON if the file ends with .json)
+    #[structopt(name = "export")]
+    Export {
+        /// File to write the profile to
+        file: String,
+    },
+
+    /// Write all pedals as described by a profile (TOML, or JSON if the file ends with .json)
+    #[structopt(name = "import")]
+    Import {
+        /// File to read the profile from
+        file: String,
+    },
+
+    /// Write a profile (a file or the name of a stored profile), but only the pedals that differ
+    #[structopt(name = "apply")]
+    Apply {
+        /// Profile to apply
+        profile: String,
+    },
+
+    /// Compare all pedals with a profile (a file or the name of a stored profile)
+    #[structopt(name = "diff")]
+    Diff {
+        /// Profile to compare with
+        profile: String,
+    },
+
+    /// Save the raw memory of all pedals, in hex (or binary if the file ends with .bin)
+    #[structopt(name = "backup")]
+    Backup {
+        /// File to write the backup to
+        file: String,
+    },
+
+    /// Write the raw memory of all pedals from a backup
+    #[structopt(name = "restore")]
+    Restore {
+        /// File to read the backup from
+        file: String,
+    },
+
+    /// Manage named profiles, stored in $XDG_CONFIG_HOME/footswitch-rs/profiles/
+    #[structopt(name = "profile")]
+    Profile {
+        #[structopt(subcommand)]
+        cmd: ProfileCommand
+    },
+
+    /// List all connected foot switches that are supported
+    #[structopt(name = "devices")]
+    Devices {
+        /// Print the list in JSON format
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Create a virtual foot switch through /dev/uhid (Linux only)
+    #[structopt(name = "emulate")]
+    Emulate {
+        /// File in which the configuration of the virtual pedals is stored
+        #[structopt(short = "s", long = "state")]
+        state: Option<String>,
+    },
+
+    /// Print a udev rule that allows users to access the foot switches without root
+    #[structopt(name = "udev-rule")]
+    UdevRule {
+        /// Grant access to all members of this group
+        #[structopt(short = "g", long = "group", raw(conflicts_with = "\"uaccess\""))]
+        group: Option<String>,
+
+        /// Grant access to the user who is logged in locally (default)
+        #[structopt(long = "uaccess")]
+        uaccess: bool,
+    },
+
+    /// Check the foot switch and its setup for common problems
+    #[structopt(name = "doctor")]
+    Doctor,
+
+    /// Wait for foot switches to be plugged in and apply a profile to them (Linux only)
+    #[structopt(name = "watch")]
+    Watch {
+        /// Profile (a file or a named profile) for foot switches without a profile for their serial number
+        #[structopt(short = "p", long = "profile")]
+        profile: Option<String>,
+
+        /// Profile for the foot switch with a serial number, given as <serial>=<profile>
+        #[structopt(short = "s", long = "serial-profile", raw(number_of_values = "1"), parse(try_from_str = "parse_serial_profile"))]
+        serial_profile: Vec<(String, String)>,
+    },
+
+    /// Print every press and release of a pedal until the application is killed
+    #[structopt(name = "listen")]
+    Listen {
+        /// Print every event as a JSON object on its own line
+        #[structopt(long = "json")]
+        json: bool,
+    },
+
+    /// Program the pedals to F13 to F15 and run commands when they are pressed (Linux only)
+    #[structopt(name = "daemon")]
+    Daemon {
+        /// Command that is run when a pedal is pressed, given as <pedal>=<command>
+        #[structopt(long = "on-press", raw(number_of_values = "1"), parse(try_from_str = "parse_pedal_command"))]
+        on_press: Vec<(usize, String)>,
+
+        /// Command that is run when a pedal is released, given as <pedal>=<command>
+        #[structopt(long = "on-release", raw(number_of_values = "1"), parse(try_from_str = "parse_pedal_command"))]
+        on_release: Vec<(usize, String)>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum ProfileCommand {
+    /// Save the configuration of all pedals as a named profile
+    #[structopt(name = "save")]
+    Save {
+        /// Name of the profile
+        name: String,
+    },
+
+    /// Write all pedals as described by a named profile
+    #[structopt(name = "load")]
+    Load {
+        /// Name of the profile
+        name: String,
+    },
+
+    /// List all named profiles
+    #[structopt(name = "list")]
+    List,
+
+    /// Print the pedals of a named profile
+    #[structopt(name = "show")]
+    Show {
+        /// Name of the profile
+        name: String,
+    },
+
+    /// Delete a named profile
+    #[structopt(name = "delete")]
+    Delete {
+        /// Name of the profile
+        name: String,
+    },
 }
 
 #[derive(StructOpt, Debug)]
@@ -163,7 +370,10 @@ enum Append {
 fn main() {
     let opt = Opt::from_args();
 
-    welcome();
+    // Machine-readable output must not be mixed with the decorations around it
+    if !is_machine_readable(&opt.cmd) {
+        welcome();
+    }
 
     // All options that don't need the device to be open
     // Print all keys and exit application
@@ -172,12 +382,131 @@ fn main() {
             key_operations::print_key_map(columns);
             goodbye();
         },
+        Some(Command::Devices { json }) => {
+            print_devices(json, &device_table(&opt));
+
+            if json {
+                process::exit(0);
+            }
+
+            goodbye();
+        },
+        Some(Command::Emulate { ref state }) => {
+            emulate(state);
+            goodbye();
+        },
+        Some(Command::Doctor) => {
+            let checks = doctor::diagnose(&device_table(&opt), &device_selector(&opt));
+            print_checks(&checks);
+
+            if checks.iter().any(|check| check.status == doctor::Status::Failed) {
+                goodbye_with(EXIT_PROBLEMS);
+            }
+
+            goodbye();
+        },
+        Some(Command::Watch { ref profile, ref serial_profile }) => {
+            let mut profiles = config::load(&config::config_file()).unwrap_or_else(|e| error!("{}", e)).watch;
+
+            if profile.is_some() {
+                profiles.profile = profile.clone();
+            }
+
+            profiles.serial.extend(serial_profile.iter().cloned());
+
+            if profiles.profile.is_none() && profiles.serial.is_empty() {
+                error!("There is no profile to apply. Use --profile or --serial-profile, or configure them in {}.",
+                       config::config_file().display());
+            }
+
+            watch(&opt, &profiles);
+        },
+        Some(Command::Listen { json }) => {
+            listen(&opt, json);
+        },
+        Some(Command::Daemon { ref on_press, ref on_release }) => {
+            let mut settings = config::load(&config::config_file()).unwrap_or_else(|e| error!("{}", e)).daemon;
+
+            for &(pedal, ref command) in on_press.iter() {
+                binding_mut(&mut settings.pedal, pedal).press = Some(command.clone());
+            }
+
+            for &(pedal, ref command) in on_release.iter() {
+                binding_mut(&mut settings.pedal, pedal).release = Some(command.clone());
+            }
+
+            if settings.pedal.is_empty() {
+                error!("There are no commands to run. Use --on-press or --on-release, or configure them in {}.",
+                       config::config_file().display());
+            }
+
+            run_daemon(&opt, &settings);
+        },
+        Some(Command::UdevRule { ref group, uaccess }) => {
+            let access = match *group {
+                Some(ref group) => {
+                    if users::get_group_by_name(group).is_none() {
+                        eprintln!("Warning: group '{}' does not exist on this system.", group);
+                    }
+
+                    udev_rule::Access::Group(group.clone())
+                },
+                None => {
+                    if !uaccess {
+                        eprintln!("Note: granting access to the local user through the uaccess tag. Use --group to grant access to a group instead.");
+                    }
+
+                    udev_rule::Access::Uaccess
+                },
+            };
+
+            print!("{}", udev_rule::rule(&device_table(&opt), &access));
+            process::exit(0);
+        },
+        Some(Command::Profile { cmd: ProfileCommand::List }) => {
+            let names = profile::list_named().unwrap_or_else(|e| fail(&e));
+
+            if names.is_empty() {
+                warning!("There are no profiles in {}.", profile::profiles_dir().display());
+            }
+
+            for name in names.iter() {
+                info!("{}", name);
+            }
+
+            goodbye();
+        },
+        Some(Command::Profile { cmd: ProfileCommand::Show { ref name } }) => {
+            let configs = profile::load_named(name).and_then(|profile| profile.to_configs()).unwrap_or_else(|e| fail(&e));
+
+            print_table(&format!("Profile '{}'", name), &describe_configs(&configs));
+            goodbye();
+        },
+        Some(Command::Profile { cmd: ProfileCommand::Delete { ref name } }) => {
+            profile::delete_named(name).unwrap_or_else(|e| fail(&e));
+
+            info!("Deleted profile '{}'.", name);
+            goodbye();
+        },
         _ => { /* Do nothing, there are still lots of other options further below */ }
     }
 
-    check_sudo();
+    // A dry run only reads the current state of a device that was selected explicitly
+    let (transport, has_device): (Box<dyn Transport>, bool) = if opt.dry_run {
+        if !supports_dry_run(&opt.cmd) {
+            error!("--dry-run can only be used with set, append, clear, import, profile load, and restore.");
+        }
+
+        match open_dry_run_transport(&opt) {
+            Some(transport) => (transport, true),
+            None => (Box::new(MockTransport::new()), false),
+        }
+    }
+    else {
+        (open_transport(&opt), true)
+    };
 
-    let mut pedals = pedal_operations::Pedals::new();
+    let mut pedals = pedal_operations::Pedals::with_transport(transport);
 
     // Make sure that the application does not purge pedals that are not explicitly set
     // by refreshing pedals that are not in use.
@@ -212,10 +541,21 @@ fn main() {
                 }
             }
         }
+        Some(Command::Export { .. }) |
+        Some(Command::Diff { .. }) |
+        Some(Command::Profile { cmd: ProfileCommand::Save { .. } }) => {
+            unused_pedals = vec![0, 1, 2];
+        }
         _ => { /* Do nothing, statement below will cover this */ }
     }
 
-    pedals.refresh_values(unused_pedals);
+    if has_device {
+        pedals.refresh_values(unused_pedals).unwrap_or_else(|e| fail(&e));
+    }
+
+    // Without a device, the values of the pedals that are not modified are unknown,
+    // so a dry run only shows the modified ones
+    let peds = if has_device { vec![0, 1, 2] } else { modified_pedals(&opt.cmd) };
 
     // All options that need the device to be open
     match opt.cmd {
@@ -227,7 +567,7 @@ fn main() {
                     check_length(&pedal, &input);
 
                     for (i, pedal) in pedal.iter().enumerate() {
-                        pedals.append_key(*pedal as usize, input[i].as_str());
+                        pedals.append_key(*pedal as usize, input[i].as_str()).unwrap_or_else(|e| fail(&e));
                     }
                 },
                 Append::AppendString { pedal, input } =>
@@ -235,7 +575,7 @@ fn main() {
                     check_length(&pedal, &input);
 
                     for (i, pedal) in pedal.iter().enumerate() {
-                        pedals.set_string(*pedal as usize, input[i].as_str());
+                        pedals.set_string(*pedal as usize, input[i].as_str()).unwrap_or_else(|e| fail(&e));
                     }
                 },
                 Append::AppendModifier { pedal, input } =>
@@ -243,12 +583,13 @@ fn main() {
                     check_length(&pedal, &input);
 
                     for (i, pedal) in pedal.iter().enumerate() {
-                        pedals.set_modifier(*pedal as usize, input[i].as_str());
+                        pedals.set_modifier(*pedal as usize, input[i].as_str()).unwrap_or_else(|e| fail(&e));
                     }
                 }
             }
 
-            pedals.update_and_close();
+            update_and_close(&pedals, &peds, opt.retries, opt.dry_run).unwrap_or_else(|e| fail(&e));
+            goodbye();
         },
 
         Some(Command::Set { cmd }) => {
@@ -258,7 +599,7 @@ fn main() {
                     check_length(&pedal, &input);
 
                     for (i, pedal) in pedal.iter().enumerate() {
-                        pedals.set_key(*pedal as usize, input[i].as_str());
+                        pedals.set_key(*pedal as usize, input[i].as_str()).unwrap_or_else(|e| fail(&e));
                     }
                 },
                 Set::SetMousebutton { pedal, input } =>
@@ -266,7 +607,7 @@ fn main() {
                     check_length(&pedal, &input);
 
                     for (i, pedal) in pedal.iter().enumerate() {
-                        pedals.set_mousebutton(*pedal as usize, input[i].as_str());
+                        pedals.set_mousebutton(*pedal as usize, input[i].as_str()).unwrap_or_else(|e| fail(&e));
                     }
                 }
 
@@ -278,18 +619,20 @@ fn main() {
                     }
 
                     for (i, pedal) in pedal.iter().enumerate() {
-                        pedals.set_mouse_xyw(*pedal as usize, x[i], 5);
-                        pedals.set_mouse_xyw(*pedal as usize, y[i], 6);
-                        pedals.set_mouse_xyw(*pedal as usize, w[i], 7);
+                        pedals.set_mouse_xyw(*pedal as usize, x[i], 5).unwrap_or_else(|e| fail(&e));
+                        pedals.set_mouse_xyw(*pedal as usize, y[i], 6).unwrap_or_else(|e| fail(&e));
+                        pedals.set_mouse_xyw(*pedal as usize, w[i], 7).unwrap_or_else(|e| fail(&e));
                     }
                 }
             }
 
-            pedals.update_and_close();
+            update_and_close(&pedals, &peds, opt.retries, opt.dry_run).unwrap_or_else(|e| fail(&e));
+            goodbye();
         },
 
         Some(Command::Clear { .. }) => {
-            pedals.update_and_close();
+            update_and_close(&pedals, &peds, opt.retries, opt.dry_run).unwrap_or_else(|e| fail(&e));
+            goodbye();
         },
 
         Some(Command::Read {all: all_var, pedals: ped_list}) => {
@@ -298,10 +641,10 @@ fn main() {
             }
 
             if all_var {
-                pedals.read_pedals(vec![0,1,2]);
+                print_pedals(&pedals, vec![0,1,2]).unwrap_or_else(|e| fail(&e));
             }
             else if ped_list.len() > 0 {
-                pedals.read_pedals(ped_list)
+                print_pedals(&pedals, ped_list).unwrap_or_else(|e| fail(&e));
             }
             else {
                 error!("You did not specify any command. Run './footswitch-rs read --help' for more information");
@@ -310,17 +653,722 @@ fn main() {
             goodbye();
         },
 
-        Some(Command::ListKeys { columns:_columns }) => { /* This case will never occur */ },
+        Some(Command::Export { file }) => {
+            current_profile(&pedals).save(Path::new(&file)).unwrap_or_else(|e| fail(&e));
+
+            info!("Saved the configuration of all pedals to '{}'.", file);
+            goodbye();
+        },
+
+        Some(Command::Import { file }) => {
+            apply_profile(&mut pedals, Profile::load(Path::new(&file)), &peds, opt.retries, opt.dry_run).unwrap_or_else(|e| fail(&e));
+            goodbye();
+        },
+
+        Some(Command::Apply { profile }) => {
+            let configs = profile::load_file_or_named(&profile).and_then(|profile| profile.to_configs()).unwrap_or_else(|e| fail(&e));
+
+            let changed = pedals.apply(&configs, opt.retries).map_err(save_snapshot).unwrap_or_else(|e| fail(&e));
+
+            if changed.is_empty() {
+                info!("The device already matches profile '{}'. Nothing was written.", profile);
+            }
+            else {
+                let changed: Vec<String> = changed.iter().map(|ped| ped.to_string()).collect();
+                info!("Wrote and verified pedal(s) {}.", changed.join(", "));
+            }
+
+            goodbye();
+        },
+
+        Some(Command::Diff { profile }) => {
+            let wanted = profile::load_file_or_named(&profile).and_then(|profile| profile.to_configs()).unwrap_or_else(|e| fail(&e));
+            let current = (0..3).map(|ped| pedals.config(ped).cloned()).collect::<Result<Vec<_>>>().unwrap_or_else(|e| fail(&e));
+
+            let diffs = profile::diff(&current, &wanted);
+
+            if diffs.is_empty() {
+                info!("The device matches profile '{}'.", profile);
+                goodbye();
+            }
+
+            for diff in diffs.iter() {
+                warning!("{}", diff);
+            }
+
+            goodbye_with(EXIT_DIFFERENT);
+        },
+
+        Some(Command::Backup { file }) => {
+            let backup = pedals.backup().unwrap_or_else(|e| fail(&e));
+
+            backup.save(Path::new(&file)).unwrap_or_else(|e| fail(&e));
+
+            info!("Saved the memory of all pedals to '{}'.", file);
+            goodbye();
+        },
+
+        Some(Command::Restore { file }) => {
+            let backup = Backup::load(Path::new(&file)).unwrap_or_else(|e| fail(&e));
+
+            pedals.set_backup(&backup).unwrap_or_else(|e| fail(&e));
+
+            update_and_close(&pedals, &peds, opt.retries, opt.dry_run).unwrap_or_else(|e| fail(&e));
+            goodbye();
+        },
+
+        Some(Command::Profile { cmd: ProfileCommand::Save { name } }) => {
+            let path = profile::save_named(&name, &current_profile(&pedals)).unwrap_or_else(|e| fail(&e));
+
+            info!("Saved the configuration of all pedals as profile '{}' ({}).", name, path.display());
+            goodbye();
+        },
+
+        Some(Command::Profile { cmd: ProfileCommand::Load { name } }) => {
+            apply_profile(&mut pedals, profile::load_named(&name), &peds, opt.retries, opt.dry_run).unwrap_or_else(|e| fail(&e));
+            goodbye();
+        },
+
+        Some(Command::ListKeys { .. }) |
+        Some(Command::Profile { .. }) |
+        Some(Command::Devices { .. }) |
+        Some(Command::Emulate { .. }) |
+        Some(Command::UdevRule { .. }) |
+        Some(Command::Doctor) |
+        Some(Command::Watch { .. }) |
+        Some(Command::Listen { .. }) |
+        Some(Command::Daemon { .. }) => { /* This case will never occur */ },
         None => {
             error!("You did not specify any command. Run './footswitch-rs --help' for more information.");
         }
     }
 }
 
-/// Checks if user is super user
-fn check_sudo() {
-    if users::get_current_uid() != 0 {
-        error!("Please execute this application as super user!");
+/// Opens the device that was selected with --device, --serial, or --index
+fn open_transport(opt: &Opt) -> Box<dyn Transport> {
+    let (transport, identity) = open_untraced_transport(opt);
+
+    wrap_transport(opt, transport, &identity)
+}
+
+/// Records and traces the packets of a transport, as requested by the options.
+/// `identity` names the device in the header of the session.
+fn wrap_transport(opt: &Opt, mut transport: Box<dyn Transport>, identity: &str) -> Box<dyn Transport> {
+
+    if let Some(ref file) = opt.record {
+        let log = match File::create(file) {
+            Ok(log) => log,
+            Err(e) => fail(&io_failure(&format!("Could not create session file '{}'", file), e)),
+        };
+
+        let description = format!("Recorded by footswitch-rs {}: {}\nDevice: {}",
+                                  env!("CARGO_PKG_VERSION"), env::args().collect::<Vec<_>>().join(" "), identity);
+
+        transport = match Recorder::new(transport, Box::new(log), &description) {
+            Ok(recorder) => Box::new(recorder),
+            Err(e) => fail(&io_failure(&format!("Could not write session file '{}'", file), e)),
+        };
+
+        info!("Recording all packets to '{}'.", file);
+    }
+
+    match trace_log(opt) {
+        Some(log) => Box::new(Trace::new(transport, log)),
+        None => transport,
+    }
+}
+
+/// Destination of the packet trace, as selected with --trace, --trace-file, or FOOTSWITCH_TRACE
+fn trace_log(opt: &Opt) -> Option<Box<dyn Write>> {
+    let target = match opt.trace_file {
+        Some(ref file) => file.clone(),
+        None if opt.trace => "stderr".to_string(),
+        None => env::var(TRACE_VARIABLE).unwrap_or_default(),
+    };
+
+    match target.as_str() {
+        "" | "0" => None,
+        "stderr" | "1" => Some(Box::new(io::stderr())),
+        file => match OpenOptions::new().create(true).append(true).open(file) {
+            Ok(file) => Some(Box::new(file)),
+            Err(e) => fail(&io_failure(&format!("Could not open trace file '{}'", target), e)),
+        },
+    }
+}
+
+/// Opens the selected device, and returns it together with a description of it
+fn open_untraced_transport(opt: &Opt) -> (Box<dyn Transport>, String) {
+    if let Some(ref device) = opt.device {
+        if let Some(path) = device.strip_prefix("emulator:") {
+            info!("Using emulated foot switch with state file '{}'.", path);
+
+            return match emulator::Emulator::open(path) {
+                Ok(emulator) => (Box::new(emulator), format!("emulator with state file {}", path)),
+                Err(e) => fail(&io_failure("Could not load emulator state", e)),
+            };
+        }
+
+        if let Some(path) = device.strip_prefix("replay:") {
+            info!("Replaying the recorded session '{}'.", path);
+
+            return match Replay::open(path) {
+                Ok(replay) => (Box::new(replay), format!("replay of {}", path)),
+                Err(e) => fail(&io_failure("Could not load session", e)),
+            };
+        }
+    }
+
+    info!("Initializing HID object. This can take a moment.");
+
+    match pedal_operations::open_identified_device(&device_selector(opt), &device_table(opt)) {
+        Ok((dev, identity)) => {
+            info!("Successfully opened device.");
+            (Box::new(dev), identity)
+        },
+        Err(e) => fail(&e),
+    }
+}
+
+/// Prints a table with the current values of the given pedals
+fn print_pedals<T: Transport>(pedals: &pedal_operations::Pedals<T>, peds: Vec<u8>) -> Result<()> {
+    let mut rows = Vec::new();
+
+    for ped in peds.iter() {
+        // Check if passed pedal number is valid
+        if *ped > 2 {
+            return Err(FootswitchError::InvalidPedal(*ped as usize));
+        }
+
+        let key_name = match pedals.describe_pedal(*ped)? {
+            Some(key) => key,
+            None => "< None >".to_string(),
+        };
+
+        rows.push((*ped, key_name));
+    }
+
+    print_table("Programmed Keys", &rows);
+
+    Ok(())
+}
+
+fn describe_configs(configs: &[PedalConfig]) -> Vec<(u8, String)> {
+    configs.iter().enumerate().map(|(ped, config)| (ped as u8, config.to_string())).collect()
+}
+
+/// Prints a table with one row per pedal
+fn print_table(title: &str, rows: &[(u8, String)]) {
+    let total_width = 55;
+
+    // Print header
+    println!("├{}┐", "─".repeat(total_width));
+    println!("│{name:^width$}│", name = title, width = total_width);
+    println!("╞{}╡", "═".repeat(total_width));
+
+    for (i, &(ped, ref key_name)) in rows.iter().enumerate() {
+        println!("│  Pedal {ped}  │  {name:<-width$}│", ped = ped, name = key_name, width = total_width - 14);
+
+        // Print spacer between lines
+        if i != rows.len() - 1 {
+            println!("│ {}┼{name:<-width$}│", "─".repeat(10), name = "─".repeat(total_width - 14), width = total_width - 12);
+        }
+    }
+
+    // Print simple footer
+    println!("├{}┘", "─".repeat(total_width));
+}
+
+/// Profile with the configuration of all pedals, as it was read from the device
+fn current_profile<T: Transport>(pedals: &pedal_operations::Pedals<T>) -> Profile {
+    let configs = (0..3).map(|ped| pedals.config(ped).cloned()).collect::<Result<Vec<_>>>().unwrap_or_else(|e| fail(&e));
+
+    Profile::from_configs(&configs)
+}
+
+/// Writes all pedals as described by a profile
+fn apply_profile<T: Transport>(pedals: &mut pedal_operations::Pedals<T>, profile: Result<Profile>, peds: &[usize], retries: usize, dry_run: bool) -> Result<()> {
+    for (ped, config) in profile?.to_configs()?.into_iter().enumerate() {
+        pedals.set_config(ped, config)?;
+    }
+
+    update_and_close(pedals, peds, retries, dry_run)
+}
+
+/// Update device, verify that it stored everything, and show the user its new state
+fn update_and_close<T: Transport>(pedals: &pedal_operations::Pedals<T>, peds: &[usize], retries: usize, dry_run: bool) -> Result<()> {
+    if dry_run {
+        return print_dry_run(pedals, peds);
+    }
+
+    pedals.write_transaction(peds, retries).map_err(save_snapshot)?;
+
+    info!("Successfully wrote everything to footpedal and verified the result!");
+    info!("The current state of the device is shown below.");
+
+    // Show user current state of pedal
+    print_pedals(pedals, vec![0,1,2])
+}
+
+/// If a write failed and the previous state of the device could not be
+/// restored, saves the snapshot that was taken before writing, so that the
+/// user can restore it later
+fn save_snapshot(err: FootswitchError) -> FootswitchError {
+    if let FootswitchError::WriteFailed { rollback: Rollback::Failed(_), ref snapshot, .. } = err {
+        let path = env::temp_dir().join(format!("footswitch-rs-snapshot-{}.txt", process::id()));
+
+        match snapshot.save(&path) {
+            Ok(()) => {
+                warning!("The state of the device before writing was saved to '{}'. Run 'footswitch-rs restore {}' to restore it.",
+                         path.display(), path.display());
+            },
+            Err(e) => {
+                warning!("The state of the device before writing could not be saved: {}", e);
+            },
+        }
+    }
+
+    err
+}
+
+/// Prints the packets that would be written and the resulting state of the pedals
+fn print_dry_run<T: Transport>(pedals: &pedal_operations::Pedals<T>, peds: &[usize]) -> Result<()> {
+    info!("Dry run: nothing is written. The following packets would be sent to the device:");
+
+    let mut describer = protocol::Describer::new();
+
+    for packet in pedals.packets(peds)?.iter() {
+        println!("│   {:<23}   {}", protocol::to_hex(packet), describer.outgoing(packet));
+    }
+
+    let mut rows = Vec::new();
+
+    for ped in peds.iter() {
+        rows.push((*ped as u8, pedals.config(*ped)?.to_string()));
+    }
+
+    print_table("Pedals After Writing", &rows);
+
+    Ok(())
+}
+
+/// Opens the device from which a dry run reads the current state, if one was
+/// selected. Returns `None` if no device was selected or if it can not be
+/// opened (e.g., without permission), so the dry run continues without one.
+fn open_dry_run_transport(opt: &Opt) -> Option<Box<dyn Transport>> {
+    if opt.device.as_ref().is_some_and(|device| device.starts_with("emulator:") || device.starts_with("replay:")) {
+        return Some(open_transport(opt));
+    }
+
+    let selector = device_selector(opt);
+
+    if !selector.is_set() {
+        info!("No device is opened, so only the modified pedals are shown. \
+               Select one with --device, --serial, or --index to read its current state.");
+        return None;
+    }
+
+    match pedal_operations::open_identified_device(&selector, &device_table(opt)) {
+        Ok((dev, identity)) => {
+            info!("Reading the current state of the pedals from the device.");
+            Some(wrap_transport(opt, Box::new(dev), &identity))
+        },
+        Err(e) => {
+            warning!("No foot switch could be opened ({}), so the current state of the pedals is unknown. \
+                      Only the modified pedals are shown.", e);
+            None
+        },
+    }
+}
+
+/// Pedals that a command modifies
+fn modified_pedals(cmd: &Option<Command>) -> Vec<usize> {
+    let mut peds: Vec<usize> = match *cmd {
+        Some(Command::Set { cmd: Set::SetKey { ref pedal, .. } }) |
+        Some(Command::Set { cmd: Set::SetMousebutton { ref pedal, .. } }) |
+        Some(Command::Set { cmd: Set::SetMousemovement { ref pedal, .. } }) |
+        Some(Command::Append { cmd: Append::AppendKey { ref pedal, .. } }) |
+        Some(Command::Append { cmd: Append::AppendString { ref pedal, .. } }) |
+        Some(Command::Append { cmd: Append::AppendModifier { ref pedal, .. } }) |
+        Some(Command::Clear { ref pedal }) => pedal.iter().map(|ped| *ped as usize).collect(),
+        _ => vec![0, 1, 2],
+    };
+
+    peds.sort();
+    peds.dedup();
+
+    peds
+}
+
+/// Commands that only write to the device, and thus can be run without a device in a dry run
+fn supports_dry_run(cmd: &Option<Command>) -> bool {
+    matches!(*cmd,
+        Some(Command::Set { .. }) |
+        Some(Command::Append { .. }) |
+        Some(Command::Clear { .. }) |
+        Some(Command::Import { .. }) |
+        Some(Command::Restore { .. }) |
+        Some(Command::Profile { cmd: ProfileCommand::Load { .. } }))
+}
+
+/// Commands whose output is meant to be parsed by other programs
+fn is_machine_readable(cmd: &Option<Command>) -> bool {
+    matches!(*cmd, Some(Command::Devices { json: true }) | Some(Command::UdevRule { .. }) | Some(Command::Listen { json: true }))
+}
+
+/// Builds the table of supported devices from the built-in devices, the
+/// configuration file, and the --add-device options
+fn device_table(opt: &Opt) -> devices::DeviceTable {
+    let mut table = devices::DeviceTable::default();
+
+    let config = match config::load(&config::config_file()) {
+        Ok(config) => config,
+        Err(e) => error!("{}", e),
+    };
+
+    for id in config.device.into_iter().chain(opt.add_device.iter().cloned()) {
+        table.add(id);
+    }
+
+    table
+}
+
+/// Error for a failure outside of the communication with the foot switch, e.g., a file that can not be created
+fn io_failure<E: fmt::Display>(context: &str, err: E) -> FootswitchError {
+    FootswitchError::Io(io::Error::other(format!("{}: {}", context, err)))
+}
+
+/// Builds the criteria to pick a foot switch from the global options
+fn device_selector(opt: &Opt) -> pedal_operations::DeviceSelector {
+    pedal_operations::DeviceSelector {
+        path: opt.device.clone(),
+        serial: opt.serial.clone(),
+        index: opt.index,
+    }
+}
+
+/// Prints the result of every check of `doctor`, together with a fix for every problem
+fn print_checks(checks: &[doctor::Check]) {
+    for check in checks.iter() {
+        match check.status {
+            doctor::Status::Ok => { info!("{}", check); },
+            doctor::Status::Warning => { warning!("{}", check); },
+            doctor::Status::Failed => println!("├ {:7} — {}", "Failed".red(), check),
+            doctor::Status::Skipped => println!("├ {:7} — {}", "Skipped".dimmed(), check),
+        }
+
+        if let Some(ref fix) = check.fix {
+            println!("│   Fix: {}", fix);
+        }
+    }
+}
+
+/// Prints all interfaces of all connected, supported devices
+fn print_devices(json: bool, table: &devices::DeviceTable) {
+    let devices = devices::list_devices(table).unwrap_or_else(|e| fail(&e));
+
+    if json {
+        match serde_json::to_string_pretty(&devices) {
+            Ok(json) => println!("{}", json),
+            Err(e) => fail(&io_failure("Could not serialize device list", e)),
+        }
+
+        return;
+    }
+
+    if devices.is_empty() {
+        warning!("No supported foot switch found.");
+    }
+
+    let unknown = || "unknown".to_string();
+
+    for (i, device) in devices.iter().enumerate() {
+        info!("Device {}: {}:{}, interface {}{}", i, device.vendor_id, device.product_id, device.interface_number,
+              if device.config_interface { " (configuration)" } else { "" });
+        println!("│   Path:         {}", device.path);
+        println!("│   hidraw:       {}", device.hidraw.clone().unwrap_or_else(unknown));
+        println!("│   Serial:       {}", device.serial_number.clone().unwrap_or_else(unknown));
+        println!("│   Manufacturer: {}", device.manufacturer.clone().unwrap_or_else(unknown));
+        println!("│   Product:      {}", device.product.clone().unwrap_or_else(unknown));
+        println!("│   Accessible:   {}", if device.accessible { "yes".green() } else { "no".red() });
+    }
+
+    if devices.iter().any(|device| device.config_interface && !device.accessible) {
+        warning!("{}", udev_rule::install_instructions());
+    }
+}
+
+/// Creates a virtual foot switch and serves it until the application is killed
+#[cfg(target_os = "linux")]
+fn emulate(state: &Option<String>) {
+    let emulator = match *state {
+        Some(ref path) => match emulator::Emulator::open(path) {
+            Ok(emulator) => emulator,
+            Err(e) => fail(&io_failure("Could not load emulator state", e)),
+        },
+        None => emulator::Emulator::new(),
+    };
+
+    let pedal = match uhid::VirtualPedal::create(emulator) {
+        Ok(pedal) => pedal,
+        Err(e) => fail(&io_failure("Could not create virtual device. Make sure that the uhid module is loaded and that you can write to /dev/uhid", e)),
+    };
+
+    // The kernel needs a moment to create the hidraw nodes
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    let (keyboard, config) = pedal.hidraw_nodes();
+    info!("Created virtual foot switch 0c45:7403. Press Ctrl+C to remove it.");
+    info!("Keyboard interface: {}", keyboard.unwrap_or_else(|| "unknown".to_string()));
+    info!("Configuration interface: {}", config.unwrap_or_else(|| "unknown".to_string()));
+
+    if let Err(e) = pedal.run() {
+        fail(&io_failure("Virtual device stopped", e));
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn emulate(_state: &Option<String>) {
+    error!("Virtual devices are only supported on Linux.");
+}
+
+/// Applies profiles to foot switches that are plugged in, until the application is killed
+#[cfg(target_os = "linux")]
+fn watch(opt: &Opt, profiles: &config::Watch) -> ! {
+    let monitor = hotplug::Monitor::new(hotplug::UDEV_EVENTS).unwrap_or_else(|e| fail(&io_failure("Could not listen for devices", e)));
+    let table = device_table(opt);
+
+    // Configuration interfaces that were handled already, by path
+    let mut known = HashSet::new();
+
+    info!("Watching for foot switches. Press Ctrl+C to stop.");
+
+    // Foot switches that are connected already
+    apply_to_new_devices(&table, profiles, &mut known, opt.retries);
+
+    loop {
+        let event = monitor.next_event().unwrap_or_else(|e| fail(&io_failure("Could not receive device events", e)));
+
+        let relevant = match event.subsystem() {
+            Some("hidraw") => true,
+            Some("usb") => event.usb_id().is_some_and(|(vendor_id, product_id)| table.is_supported(vendor_id, product_id)),
+            _ => false,
+        };
+
+        if relevant {
+            apply_to_new_devices(&table, profiles, &mut known, opt.retries);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch(_opt: &Opt, _profiles: &config::Watch) -> ! {
+    error!("Watching for foot switches is only supported on Linux.");
+}
+
+/// Applies a profile to every connected foot switch that is not in `known`
+/// and forgets the ones that were removed
+#[cfg(target_os = "linux")]
+fn apply_to_new_devices(table: &devices::DeviceTable, profiles: &config::Watch, known: &mut HashSet<String>, retries: usize) {
+    let devices = match devices::list_devices(table) {
+        Ok(devices) => devices,
+        Err(e) => {
+            warning!("Could not list foot switches: {}", e);
+            return;
+        },
+    };
+
+    let connected: Vec<&devices::DeviceInfo> = devices.iter().filter(|device| device.config_interface).collect();
+
+    known.retain(|path| connected.iter().any(|device| device.path == *path));
+
+    for device in connected {
+        if !known.insert(device.path.clone()) {
+            continue;
+        }
+
+        let name = format!("{}:{} (serial: {}) at {}", device.vendor_id, device.product_id,
+                           device.serial_number.clone().unwrap_or_else(|| "unknown".to_string()), device.path);
+
+        let profile = match profiles.profile_for(device.serial_number.as_deref()) {
+            Some(profile) => profile,
+            None => {
+                info!("Connected {}. There is no profile for it.", name);
+                continue;
+            },
+        };
+
+        let selector = pedal_operations::DeviceSelector { path: Some(device.path.clone()), ..Default::default() };
+
+        let result = profile::load_file_or_named(profile)
+            .and_then(|profile| profile.to_configs())
+            .and_then(|configs| pedal_operations::Pedals::new(&selector, table)?.apply(&configs, retries))
+            .map_err(save_snapshot);
+
+        match result {
+            Ok(ref changed) if changed.is_empty() => {
+                info!("Connected {}. It already matches profile '{}'.", name, profile);
+            },
+            Ok(changed) => {
+                let changed: Vec<String> = changed.iter().map(|ped| ped.to_string()).collect();
+                info!("Connected {}. Applied profile '{}' and verified pedal(s) {}.", name, profile, changed.join(", "));
+            },
+            Err(e) => {
+                warning!("Connected {}, but could not apply profile '{}': {} Reconnect it to try again.", name, profile, e);
+            },
+        }
+    }
+}
+
+/// Prints every press and release of a pedal, until the application is killed
+fn listen(opt: &Opt, json: bool) -> ! {
+    if opt.device.as_ref().is_some_and(|device| device.starts_with("emulator:") || device.starts_with("replay:")) {
+        error!("listen needs a connected foot switch.");
+    }
+
+    let table = device_table(opt);
+    let selector = device_selector(opt);
+
+    // The configuration of the pedals tells which pedal sent a report. The
+    // configuration interface is closed before the keyboard interface is
+    // opened, because hidapi allows only one instance at a time.
+    let configs = {
+        let pedals = pedal_operations::Pedals::new(&selector, &table).unwrap_or_else(|e| fail(&e));
+
+        (0..3).map(|ped| pedals.read_config(ped)).collect::<Result<Vec<_>>>().unwrap_or_else(|e| fail(&e))
+    };
+
+    let keyboard = pedal_operations::open_keyboard(&selector, &table).unwrap_or_else(|e| fail(&e));
+    let mut listener = listen::Listener::new(keyboard, configs);
+
+    if !json {
+        info!("Listening for pedals. Press Ctrl+C to stop.");
+    }
+
+    loop {
+        let event = listener.next_event().unwrap_or_else(|e| fail(&e));
+
+        if json {
+            match serde_json::to_string(&event) {
+                Ok(line) => println!("{}", line),
+                Err(e) => fail(&io_failure("Could not serialize event", e)),
+            }
+        }
+        else {
+            let pedal = event.pedal.map(|ped| ped.to_string()).unwrap_or_else(|| "?".to_string());
+
+            info!("{:.6} pedal {} {}: {} ({})", event.timestamp, pedal, event.action, event.description, event.raw);
+        }
+    }
+}
+
+/// Programs the pedals to the keys of the daemon and runs the commands of the
+/// pedals when they are pressed, until the application is killed
+#[cfg(target_os = "linux")]
+fn run_daemon(opt: &Opt, settings: &config::Daemon) -> ! {
+    let codes = settings.keys.iter().map(|key| {
+        daemon::linux_keycode(key).unwrap_or_else(|| error!("Pedals can not be programmed to '{}' for the daemon. Use f13 to f24.", key))
+    }).collect::<Vec<u16>>();
+
+    if codes.len() != 3 || codes[0] == codes[1] || codes[0] == codes[2] || codes[1] == codes[2] {
+        error!("The daemon needs three different keys, one for every pedal.");
+    }
+
+    if let Some(binding) = settings.pedal.iter().find(|binding| binding.pedal > 2) {
+        fail(&FootswitchError::InvalidPedal(binding.pedal));
+    }
+
+    let table = device_table(opt);
+    let selector = device_selector(opt);
+    let path = pedal_operations::device_path(&selector, &table).unwrap_or_else(|e| fail(&e));
+
+    // Program every pedal to its key, unless it already is
+    {
+        let mut pedals = pedal_operations::Pedals::new(&selector, &table).unwrap_or_else(|e| fail(&e));
+
+        for (ped, key) in settings.keys.iter().enumerate() {
+            pedals.set_key(ped, key).unwrap_or_else(|e| fail(&e));
+        }
+
+        let configs = (0..3).map(|ped| pedals.config(ped).cloned()).collect::<Result<Vec<_>>>().unwrap_or_else(|e| fail(&e));
+
+        let changed = pedals.apply(&configs, opt.retries).map_err(save_snapshot).unwrap_or_else(|e| fail(&e));
+
+        if changed.is_empty() {
+            info!("The pedals are already programmed to {}.", settings.keys.join(", "));
+        }
+        else {
+            info!("Programmed the pedals to {}.", settings.keys.join(", "));
+        }
+    }
+
+    let nodes = match devices::usb_address(&path) {
+        Some((bus, address)) => devices::evdev_nodes(bus, address, devices::KEYBOARD_INTERFACE),
+        None => Vec::new(),
+    };
+
+    if nodes.is_empty() {
+        fail(&FootswitchError::DeviceNotFound(format!("Could not find the event device of the foot switch at {}.", path)));
+    }
+
+    let source = evdev::EvdevSource::grab(&nodes).unwrap_or_else(|e| fail(&FootswitchError::from(e)));
+    info!("Grabbed {}. Press Ctrl+C to stop.", nodes.join(", "));
+
+    let mut daemon = daemon::Daemon::new(source, daemon::ShellRunner, codes, settings.pedal.clone());
+
+    loop {
+        let handled = match daemon.step() {
+            Ok(Some(handled)) => handled,
+            Ok(None) => continue,
+            Err(e) => fail(&io_failure("Could not read the events of the foot switch", e)),
+        };
+
+        match handled.command {
+            Some((ref command, Ok(()))) => {
+                info!("Pedal {} {} after {} ms: running '{}'.", handled.pedal, handled.action, handled.duration.as_millis(), command);
+            },
+            Some((ref command, Err(ref e))) => {
+                warning!("Pedal {} {}, but '{}' could not be started: {}", handled.pedal, handled.action, command, e);
+            },
+            None => {
+                info!("Pedal {} {} after {} ms.", handled.pedal, handled.action, handled.duration.as_millis());
+            },
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_daemon(_opt: &Opt, _settings: &config::Daemon) -> ! {
+    error!("The daemon is only supported on Linux.");
+}
+
+/// Binding of a pedal, which is added if there is none yet
+fn binding_mut(bindings: &mut Vec<daemon::Binding>, pedal: usize) -> &mut daemon::Binding {
+    match bindings.iter().position(|binding| binding.pedal == pedal) {
+        Some(index) => &mut bindings[index],
+        None => {
+            bindings.push(daemon::Binding { pedal, ..Default::default() });
+            bindings.last_mut().unwrap()
+        },
+    }
+}
+
+/// Parses `<pedal>=<command>`
+fn parse_pedal_command(s: &str) -> std::result::Result<(usize, String), String> {
+    let mut parts = s.splitn(2, '=');
+
+    match (parts.next().and_then(|pedal| pedal.trim().parse().ok()), parts.next()) {
+        (Some(pedal), Some(command)) if !command.is_empty() => Ok((pedal, command.to_string())),
+        _ => Err(format!("Invalid command '{}'. Use <pedal>=<command>.", s)),
+    }
+}
+
+/// Parses `<serial>=<profile>`
+fn parse_serial_profile(s: &str) -> std::result::Result<(String, String), String> {
+    let mut parts = s.splitn(2, '=');
+
+    match (parts.next(), parts.next()) {
+        (Some(serial), Some(profile)) if !serial.is_empty() && !profile.is_empty() => Ok((serial.to_string(), profile.to_string())),
+        _ => Err(format!("Invalid serial profile '{}'. Use <serial>=<profile>.", s)),
     }
 }
 
@@ -1,5 +1,6 @@
 use colored::*;
 use std::process;
+use footswitch_rs::error::FootswitchError;
 
 #[macro_export]
 macro_rules! info {
@@ -15,13 +16,14 @@ macro_rules! warning {
     };
 }
 
+/// Prints an error about invalid usage and exits. All other failures go through `fail`.
 #[macro_export]
 macro_rules! error {
     ($($arg:tt)*) => { 
         {
             eprintln!("└ {:7} — {}", "Error".on_red().white(), format_args!($($arg)*));
             println!("");
-            process::exit(0);
+            process::exit(::footswitch_rs::error::EXIT_USAGE);
         }
     };
 }
@@ -38,6 +40,18 @@ pub fn welcome() {
 }
 
 pub fn goodbye() {
+    goodbye_with(0);
+}
+
+/// Says goodbye, but exits with a code that signals a result to scripts
+pub fn goodbye_with(code: i32) -> ! {
     println!("└ {:7}", "Goodbye!".green());
-    process::exit(0);
+    process::exit(code);
+}
+
+/// Prints an error and exits with the exit code that belongs to it
+pub fn fail(err: &FootswitchError) -> ! {
+    eprintln!("└ {:7} — {}", "Error".on_red().white(), err);
+    println!();
+    process::exit(err.exit_code());
 }
@@ -0,0 +1,391 @@
+//! Typed configuration of a single pedal
+//!
+//! `PedalConfig` is what a pedal does, independent of how it is stored on the
+//! device. `encode` produces the data bytes that are sent after the header
+//! packet (see `protocol`), and `decode` turns these bytes back into a
+//! `PedalConfig`.
+//!
+//! Bytes that are not used by the type of a pedal are ignored by `decode`, and
+//! the alternative key type `0x81` is decoded as `Key`. Blank pedals, i.e.,
+//! data with a length below 2 or with type 0, are decoded as `Unconfigured`.
+//! Apart from that, `encode(decode(bytes)) == bytes` holds for every
+//! configuration a pedal can store.
+
+use std::fmt;
+use key_operations;
+use key_operations::{Modifier, MouseButton};
+use protocol;
+use error::{FootswitchError, Result};
+
+/// Type of the function of a pedal, as stored in the second byte of its data
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Type {
+    Unconfigured = 0,
+    Key = 1,
+    Mouse = 2,
+    MouseKey = 3,
+    String = 4
+}
+
+impl Type {
+    pub fn u8_to_enum(value:u8) -> Option<Type> {
+        match value {
+            0       => Some(Type::Unconfigured),
+            1       => Some(Type::Key),
+            2       => Some(Type::Mouse),
+            3       => Some(Type::MouseKey),
+            4       => Some(Type::String),
+            0x81    => Some(Type::Key),
+            _       => None
+        }
+    }
+}
+
+/// Maximum length of the data of a string (including the two bytes for length and type)
+pub const MAX_STRING_LEN: usize = 38;
+
+/// Length of the data of all types except strings
+const FIXED_LEN: u8 = 8;
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum PedalConfig {
+    /// The pedal does nothing
+    #[default]
+    Unconfigured,
+
+    /// A key, optionally combined with modifiers (a combination of `Modifier` values)
+    Key { modifiers: u8, key: u8 },
+
+    /// Mouse buttons (a combination of `MouseButton` values) and a movement of the pointer
+    Mouse { buttons: u8, x: i8, y: i8, w: i8 },
+
+    /// A key and a mouse action at the same time
+    MouseKey { modifiers: u8, key: u8, buttons: u8, x: i8, y: i8, w: i8 },
+
+    /// A sequence of keys that is typed one after another
+    String { keys: Vec<u8> },
+}
+
+impl PedalConfig {
+    /// Decode the data bytes of a pedal. `data` may be longer than the length
+    /// that is stored in its first byte (e.g., padding of the last packet).
+    pub fn decode(data: &[u8]) -> Result<PedalConfig> {
+        let len = match data.first() {
+            Some(len) => *len as usize,
+            None => return Err(FootswitchError::Protocol("The pedal returned no data.".to_string())),
+        };
+
+        // A length that is too short to contain a type, e.g., of a pedal that was never configured
+        if len < 2 || data.get(1) == Some(&(Type::Unconfigured as u8)) {
+            return Ok(PedalConfig::Unconfigured);
+        }
+
+        if data.len() < 2 {
+            return Err(FootswitchError::Protocol(format!("The pedal returned too little data ({} bytes).", data.len())));
+        }
+
+        let typ = match Type::u8_to_enum(data[1]) {
+            Some(typ) => typ,
+            None => return Err(FootswitchError::Protocol(
+                format!("The key type which was returned by the pedal ({:#04x}) was invalid!", data[1]))),
+        };
+
+        if let Type::String = typ {
+            if !(2..=protocol::MAX_DATA_LEN).contains(&len) {
+                return Err(FootswitchError::Protocol(format!("The pedal returned an invalid string length ({}).", len)));
+            }
+
+            if data.len() < len {
+                return Err(FootswitchError::Protocol(
+                    format!("The pedal announced a string of {} bytes, but only {} bytes were received.", len, data.len())));
+            }
+
+            return Ok(PedalConfig::String { keys: data[2..len].to_vec() });
+        }
+
+        if len != FIXED_LEN as usize || data.len() < len {
+            return Err(FootswitchError::Protocol(format!("The pedal returned an invalid data length ({}).", len)));
+        }
+
+        Ok(match typ {
+            Type::Unconfigured | Type::String => unreachable!(),
+            Type::Key => PedalConfig::Key { modifiers: data[2], key: data[3] },
+            Type::Mouse => PedalConfig::Mouse { buttons: data[4], x: data[5] as i8, y: data[6] as i8, w: data[7] as i8 },
+            Type::MouseKey => PedalConfig::MouseKey {
+                modifiers: data[2],
+                key: data[3],
+                buttons: data[4],
+                x: data[5] as i8,
+                y: data[6] as i8,
+                w: data[7] as i8,
+            },
+        })
+    }
+
+    /// Encode the configuration to the data bytes that are written after the header packet
+    pub fn encode(&self) -> Vec<u8> {
+        // Negative movements end up as their two's complement, which is what the device expects
+        let data = match *self {
+            PedalConfig::Unconfigured => protocol::unconfigured_data(),
+            PedalConfig::Key { modifiers, key } => [FIXED_LEN, Type::Key as u8, modifiers, key, 0, 0, 0, 0],
+            PedalConfig::Mouse { buttons, x, y, w } => [FIXED_LEN, Type::Mouse as u8, 0, 0, buttons, x as u8, y as u8, w as u8],
+            PedalConfig::MouseKey { modifiers, key, buttons, x, y, w } => {
+                [FIXED_LEN, Type::MouseKey as u8, modifiers, key, buttons, x as u8, y as u8, w as u8]
+            },
+            PedalConfig::String { ref keys } => {
+                let mut data = vec![(keys.len() + 2) as u8, Type::String as u8];
+                data.extend_from_slice(keys);
+
+                return data;
+            },
+        };
+
+        data.to_vec()
+    }
+
+    pub fn typ(&self) -> Type {
+        match *self {
+            PedalConfig::Unconfigured => Type::Unconfigured,
+            PedalConfig::Key { .. } => Type::Key,
+            PedalConfig::Mouse { .. } => Type::Mouse,
+            PedalConfig::MouseKey { .. } => Type::MouseKey,
+            PedalConfig::String { .. } => Type::String,
+        }
+    }
+
+    /// Number of data bytes of the encoded configuration
+    pub fn data_len(&self) -> usize {
+        match *self {
+            PedalConfig::String { ref keys } => keys.len() + 2,
+            _ => FIXED_LEN as usize,
+        }
+    }
+
+    pub fn is_unconfigured(&self) -> bool {
+        *self == PedalConfig::Unconfigured
+    }
+
+    /// Set the key. Keeps the modifiers and turns a mouse action into a combination of both.
+    pub fn set_key(&mut self, key: u8) -> Result<()> {
+        *self = match self.clone() {
+            PedalConfig::Unconfigured => PedalConfig::Key { modifiers: 0, key },
+            PedalConfig::Key { modifiers, .. } => PedalConfig::Key { modifiers, key },
+            PedalConfig::Mouse { buttons, x, y, w } => PedalConfig::MouseKey { modifiers: 0, key, buttons, x, y, w },
+            PedalConfig::MouseKey { modifiers, buttons, x, y, w, .. } => PedalConfig::MouseKey { modifiers, key, buttons, x, y, w },
+            PedalConfig::String { .. } => return Err(FootswitchError::InvalidCombination),
+        };
+
+        Ok(())
+    }
+
+    /// Add a modifier to the key
+    pub fn add_modifier(&mut self, modifier: Modifier) -> Result<()> {
+        *self = match self.clone() {
+            PedalConfig::Unconfigured => PedalConfig::Key { modifiers: modifier as u8, key: 0 },
+            PedalConfig::Key { modifiers, key } => PedalConfig::Key { modifiers: modifiers | modifier as u8, key },
+            PedalConfig::Mouse { buttons, x, y, w } => {
+                PedalConfig::MouseKey { modifiers: modifier as u8, key: 0, buttons, x, y, w }
+            },
+            PedalConfig::MouseKey { modifiers, key, buttons, x, y, w } => {
+                PedalConfig::MouseKey { modifiers: modifiers | modifier as u8, key, buttons, x, y, w }
+            },
+            PedalConfig::String { .. } => return Err(FootswitchError::InvalidCombination),
+        };
+
+        Ok(())
+    }
+
+    /// Add a mouse button. Turns a key into a combination of both.
+    pub fn add_mousebutton(&mut self, mousebutton: MouseButton) -> Result<()> {
+        self.update_mouse(|buttons, _, _, _| *buttons |= mousebutton as u8)
+    }
+
+    /// Set the movement of the mouse pointer. Turns a key into a combination of both.
+    pub fn set_movement(&mut self, x: Option<i8>, y: Option<i8>, w: Option<i8>) -> Result<()> {
+        self.update_mouse(|_, old_x, old_y, old_w| {
+            *old_x = x.unwrap_or(*old_x);
+            *old_y = y.unwrap_or(*old_y);
+            *old_w = w.unwrap_or(*old_w);
+        })
+    }
+
+    fn update_mouse<F: FnOnce(&mut u8, &mut i8, &mut i8, &mut i8)>(&mut self, update: F) -> Result<()> {
+        match *self {
+            PedalConfig::Unconfigured => {
+                *self = PedalConfig::Mouse { buttons: 0, x: 0, y: 0, w: 0 };
+            },
+            PedalConfig::Key { modifiers, key } => {
+                *self = PedalConfig::MouseKey { modifiers, key, buttons: 0, x: 0, y: 0, w: 0 };
+            },
+            PedalConfig::String { .. } => return Err(FootswitchError::InvalidCombination),
+            _ => {},
+        }
+
+        match *self {
+            PedalConfig::Mouse { ref mut buttons, ref mut x, ref mut y, ref mut w } |
+            PedalConfig::MouseKey { ref mut buttons, ref mut x, ref mut y, ref mut w, .. } => update(buttons, x, y, w),
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    /// Append keys to a string
+    pub fn append_keys(&mut self, new_keys: &[u8]) -> Result<()> {
+        if self.is_unconfigured() {
+            *self = PedalConfig::String { keys: Vec::new() };
+        }
+
+        match *self {
+            PedalConfig::String { ref mut keys } => {
+                let len = keys.len() + 2 + new_keys.len();
+
+                if len > MAX_STRING_LEN {
+                    return Err(FootswitchError::StringTooLong { len, max: MAX_STRING_LEN });
+                }
+
+                keys.extend_from_slice(new_keys);
+
+                Ok(())
+            },
+            _ => Err(FootswitchError::InvalidCombination),
+        }
+    }
+}
+
+impl fmt::Display for PedalConfig {
+    /// Prints the configuration like `read` does
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data = self.encode();
+
+        let description = match *self {
+            PedalConfig::Unconfigured => None,
+            PedalConfig::Key { .. } => key_operations::print_key(&data),
+            PedalConfig::Mouse { .. } => key_operations::print_mousebutton(&data),
+            PedalConfig::MouseKey { .. } => key_operations::print_mouse_key(&data),
+            PedalConfig::String { ref keys } => {
+                Some(keys.iter().filter_map(key_operations::decode_byte).collect())
+            },
+        };
+
+        write!(f, "{}", description.unwrap_or_else(|| "< None >".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(data: &[u8]) {
+        let config = PedalConfig::decode(data).unwrap();
+
+        assert_eq!(config.encode(), data);
+        assert_eq!(config.data_len(), data.len());
+        assert_eq!(PedalConfig::decode(&config.encode()).unwrap(), config);
+    }
+
+    #[test]
+    fn round_trip_unconfigured() {
+        assert_round_trip(&[0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn round_trip_key() {
+        // a
+        assert_round_trip(&[0x08, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00]);
+        // ctrl+alt+<del>
+        assert_round_trip(&[0x08, 0x01, 0x05, 0x4c, 0x00, 0x00, 0x00, 0x00]);
+        // only modifiers
+        assert_round_trip(&[0x08, 0x01, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn round_trip_mouse() {
+        assert_round_trip(&[0x08, 0x02, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+        // Negative movements
+        assert_round_trip(&[0x08, 0x02, 0x00, 0x00, 0x00, 0x64, 0x9c, 0x80]);
+        assert_round_trip(&[0x08, 0x02, 0x00, 0x00, 0x0f, 0x7f, 0xff, 0x01]);
+    }
+
+    #[test]
+    fn round_trip_mouse_key() {
+        assert_round_trip(&[0x08, 0x03, 0x02, 0x05, 0x01, 0x0a, 0xf6, 0x00]);
+    }
+
+    #[test]
+    fn round_trip_string() {
+        // Empty string
+        assert_round_trip(&[0x02, 0x04]);
+        // "hello", shorter than a packet
+        assert_round_trip(&[0x07, 0x04, 0x0b, 0x08, 0x0f, 0x0f, 0x12]);
+        // Exactly one packet
+        assert_round_trip(&[0x08, 0x04, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09]);
+
+        // Longest string, spread over several packets
+        let mut data = vec![MAX_STRING_LEN as u8, 0x04];
+        data.extend((0..(MAX_STRING_LEN - 2) as u8).map(|i| 0x04 + i));
+        assert_round_trip(&data);
+    }
+
+    #[test]
+    fn decode_ignores_padding() {
+        let data = [0x03, 0x04, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+        assert_eq!(PedalConfig::decode(&data).unwrap(), PedalConfig::String { keys: vec![0x04] });
+    }
+
+    #[test]
+    fn decode_normalizes_alternative_key_type() {
+        let config = PedalConfig::decode(&[0x08, 0x81, 0x01, 0x06, 0x00, 0x00, 0x00, 0x00]).unwrap();
+
+        assert_eq!(config, PedalConfig::Key { modifiers: 0x01, key: 0x06 });
+        assert_eq!(config.encode(), vec![0x08, 0x01, 0x01, 0x06, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn decode_blank_pedals() {
+        // Blank memory, a length that is too short to contain a type, and type 0 with another length
+        for data in [&[0x00; 8][..], &[0x01, 0x01, 0x00, 0x04][..], &[0x00][..], &[0x03, 0x00, 0x04][..]].iter() {
+            assert_eq!(PedalConfig::decode(data).unwrap(), PedalConfig::Unconfigured);
+        }
+    }
+
+    #[test]
+    fn decode_rejects_invalid_data() {
+        // Unknown type
+        assert!(PedalConfig::decode(&[0x08, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]).is_err());
+        // Wrong length for a key
+        assert!(PedalConfig::decode(&[0x07, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00]).is_err());
+        // String that is longer than the data
+        assert!(PedalConfig::decode(&[0x0a, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]).is_err());
+        // Too little data
+        assert!(PedalConfig::decode(&[0x08]).is_err());
+    }
+
+    #[test]
+    fn combinations() {
+        let mut config = PedalConfig::Unconfigured;
+        config.set_key(0x04).unwrap();
+        config.add_modifier(Modifier::Ctrl).unwrap();
+        assert_eq!(config, PedalConfig::Key { modifiers: 0x01, key: 0x04 });
+
+        config.add_mousebutton(MouseButton::MouseLeft).unwrap();
+        config.set_movement(Some(-1), None, None).unwrap();
+        assert_eq!(config, PedalConfig::MouseKey { modifiers: 0x01, key: 0x04, buttons: 0x01, x: -1, y: 0, w: 0 });
+
+        assert!(config.append_keys(&[0x04]).is_err());
+
+        let mut config = PedalConfig::Unconfigured;
+        config.append_keys(&[0x04, 0x05]).unwrap();
+        assert!(config.set_key(0x04).is_err());
+        assert!(config.add_mousebutton(MouseButton::MouseRight).is_err());
+
+        match config.append_keys(&[0x04; MAX_STRING_LEN]) {
+            Err(FootswitchError::StringTooLong { len, max }) => {
+                assert_eq!(len, MAX_STRING_LEN + 4);
+                assert_eq!(max, MAX_STRING_LEN);
+            },
+            _ => panic!("String should be too long"),
+        }
+    }
+}
@@ -1,327 +1,558 @@
-#[path = "key_operations.rs"] pub mod key_operations;
-
-extern crate hidapi;
-use std::process;
+use std::io;
 use std::ffi::CString;
-use colored::*;
-use messages::*;
-
-#[derive(Copy, Clone)]
-enum Type {
-    Unconfigured = 0,
-    Key = 1,
-    Mouse = 2,
-    MouseKey = 3,
-    String = 4
+use hidapi;
+use key_operations;
+use transport::{Device, Hidraw, Transport};
+use protocol;
+use devices;
+use pedal_config::{PedalConfig, Type, MAX_STRING_LEN};
+use error::{FootswitchError, Mismatch, Rollback, Result};
+use backup::Backup;
+use udev_rule;
+
+pub struct Pedals<T: Transport = Device> {
+    dev: T,
+
+    start: [u8; 8],
+    ped_data: Vec<PedalConfig>,
+
+    /// Data that is written instead of the encoded configuration (see `set_raw`)
+    ped_raw: Vec<Option<Vec<u8>>>,
 }
 
-impl Type {
-    fn u8_to_enum(value:u8) -> Option<Type> {
-        match value {
-            0       => Some(Type::Unconfigured),
-            1       => Some(Type::Key),
-            2       => Some(Type::Mouse),
-            3       => Some(Type::MouseKey),
-            4       => Some(Type::String),
-            0x81    => Some(Type::Key),
-            _       => None
+impl Pedals {
+    pub fn new(selector: &DeviceSelector, table: &devices::DeviceTable) -> Result<Pedals> {
+        Ok(Pedals::with_transport(open_device(selector, table)?))
+    }
+}
+
+/// Criteria to pick one foot switch if several of them are connected
+#[derive(Default, Debug)]
+pub struct DeviceSelector {
+    /// Path of the device, as reported by hidapi
+    pub path: Option<String>,
+
+    /// Serial number of the device
+    pub serial: Option<String>,
+
+    /// Position of the device in the list of all matching devices
+    pub index: Option<usize>,
+}
+
+impl DeviceSelector {
+    /// Checks if any criterion was given
+    pub fn is_set(&self) -> bool {
+        self.path.is_some() || self.serial.is_some() || self.index.is_some()
+    }
+
+    fn matches(&self, device: &hidapi::HidDeviceInfo) -> bool {
+        if let Some(ref path) = self.path {
+            if device.path.to_str() != Ok(path.as_str()) {
+                return false;
+            }
+        }
+
+        if let Some(ref serial) = self.serial {
+            if device.serial_number.as_ref() != Some(serial) {
+                return false;
+            }
         }
+
+        true
     }
 }
 
-pub struct PedalsData {
-    header: [u8; 8],
-    data: [u8; 48],
-    length: u8,
+/// Look for a supported foot switch and open its configuration interface
+pub fn open_device(selector: &DeviceSelector, table: &devices::DeviceTable) -> Result<Device> {
+    open_identified_device(selector, table).map(|(dev, _)| dev)
 }
 
-pub struct Pedals {
-    dev:hidapi::HidDevice,
+/// Like `open_device`, but also returns a description of the device, e.g.,
+/// `0c45:7403 at 1-2:1.1 (serial: unknown)`
+pub fn open_identified_device(selector: &DeviceSelector, table: &devices::DeviceTable) -> Result<(Device, String)> {
+    let api = init_api()?;
+    let devices = devices::enumerate(&api, table);
+    let dev_path = select_from(&devices, selector, table)?;
 
-    start: [u8; 8],
-    ped_data: Vec<PedalsData>,
+    let identity = match devices.iter().find(|device| device.path == dev_path) {
+        Some(device) => describe_device(device),
+        None => format!("unknown device at {}", dev_path.to_string_lossy()),
+    };
+
+    Ok((open_path(&api, &dev_path)?, identity))
 }
 
-impl Pedals {
-    pub fn new() -> Pedals {
-        // Open device
-        let vld_dev = [
-            (0x0c45u16, 0x7403u16),
-            (0x0c45   , 0x7404),
-            (0x413d   , 0x2107)
-        ];
+/// Look for a supported foot switch and open its keyboard interface, through
+/// which the pedals send what they are configured to when they are pressed
+pub fn open_keyboard(selector: &DeviceSelector, table: &devices::DeviceTable) -> Result<Device> {
+    let api = init_api()?;
+    let config_path = select_device(&api, selector, table)?.to_string_lossy().to_string();
+
+    let devices = devices::enumerate(&api, table);
+    let keyboards: Vec<&hidapi::HidDeviceInfo> = devices.iter()
+        .filter(|device| table.is_supported(device.vendor_id, device.product_id) && device.interface_number == devices::KEYBOARD_INTERFACE)
+        .collect();
+
+    // The keyboard interface belongs to the same USB device as the configuration interface
+    let address = devices::usb_address(&config_path);
+
+    let keyboard = match keyboards.iter().find(|device| address.is_some() && devices::usb_address(&device.path.to_string_lossy()) == address) {
+        Some(keyboard) => Some(keyboard),
+        None if keyboards.len() == 1 => keyboards.first(),
+        None => None,
+    };
+
+    match keyboard {
+        Some(keyboard) => open_path(&api, &keyboard.path),
+        None => Err(FootswitchError::DeviceNotFound(
+            format!("Could not find the keyboard interface of the foot switch at {}.", config_path))),
+    }
+}
 
-        info!("Initializing HID object. This can take a moment.");
+/// Path of the configuration interface of the foot switch that `selector` selects, without opening it
+pub fn device_path(selector: &DeviceSelector, table: &devices::DeviceTable) -> Result<String> {
+    Ok(select_device(&init_api()?, selector, table)?.to_string_lossy().to_string())
+}
 
-        let api = match hidapi::HidApi::new() {
-            Ok(res) => {
-                info!("Successfully initialized HID object.");
-                res
-            },
-            Err(_) => {
-                error!("Could not initialize HID object.")
-            },
-        };
+fn init_api() -> Result<hidapi::HidApi> {
+    hidapi::HidApi::new()
+        .map_err(|e| FootswitchError::Io(io::Error::other(format!("Could not initialize HID object: {}", e))))
+}
 
-        let mut dev_path = CString::new("").unwrap();
+/// Path of the configuration interface of the foot switch that `selector` selects
+fn select_device(api: &hidapi::HidApi, selector: &DeviceSelector, table: &devices::DeviceTable) -> Result<CString> {
+    select_from(&devices::enumerate(api, table), selector, table)
+}
 
-        for device in api.devices() {
-            for val in vld_dev.iter() {
-                if *val == (device.vendor_id, device.product_id) && device.interface_number == 1 {
-                    info!("Found device {:x}:{:x} ({:#?})", device.vendor_id, device.product_id, device.path);
-                    dev_path = device.path.clone();
-                }
-            }
+/// Path of the configuration interface that `selector` selects among the given devices
+fn select_from(devices: &[hidapi::HidDeviceInfo], selector: &DeviceSelector, table: &devices::DeviceTable) -> Result<CString> {
+    let mut candidates = Vec::new();
+
+    for device in devices.iter() {
+        if table.is_config_interface(device) && selector.matches(device) {
+            candidates.push(device);
         }
+    }
 
-        // Moved this out of loop, because of error of "possibly uninitialized `dev`. Don't try to move it in the loop.
-        let dev = match api.open_path(&dev_path) {
-            Ok(res) => {
-                info!("Successfully opened device.");
-                res
+    let dev_path = match selector.index {
+        Some(index) => match candidates.get(index) {
+            Some(device) => device.path.clone(),
+            None => return Err(FootswitchError::DeviceNotFound(
+                format!("There is no matching device with index {}. Found {} matching device(s).", index, candidates.len()))),
+        },
+        None if candidates.len() == 1 => candidates[0].path.clone(),
+        None if candidates.len() > 1 => {
+            return Err(FootswitchError::MultipleDevices(candidates.iter().map(|device| describe_device(device)).collect()));
+        },
+        None => match selector.path {
+            // The device might not be enumerated (e.g., virtual devices), so try to open it directly
+            Some(ref path) => match CString::new(path.as_str()) {
+                Ok(path) => path,
+                Err(_) => return Err(FootswitchError::DeviceNotFound(format!("Invalid device path '{}'.", path))),
             },
-            Err(_) => {
-                error!("Could not open device. Make sure your device is connected. Maybe try to reconnect it.")
-            },
-        };
+            None if selector.is_set() => return Err(FootswitchError::DeviceNotFound(
+                "Could not find a foot switch that matches the given selection.".to_string())),
+            None => return Err(FootswitchError::DeviceNotFound(
+                "Could not find a supported foot switch. Make sure your device is connected. Maybe try to reconnect it.".to_string())),
+        },
+    };
+
+    Ok(dev_path)
+}
 
-        // Prepare variables
-        let start = [0x01u8, 0x80, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00];
+fn describe_device(device: &hidapi::HidDeviceInfo) -> String {
+    format!("{:04x}:{:04x} at {} (serial: {})", device.vendor_id, device.product_id, device.path.to_string_lossy(),
+            device.serial_number.clone().unwrap_or_else(|| "unknown".to_string()))
+}
 
-        let header_0 = [0x01u8, 0x81, 0x08, 0x01, 0x00, 0x00, 0x00, 0x00];
-        let header_1 = [0x01u8, 0x81, 0x08, 0x02, 0x00, 0x00, 0x00, 0x00];
-        let header_2 = [0x01u8, 0x81, 0x08, 0x03, 0x00, 0x00, 0x00, 0x00];
+fn open_path(api: &hidapi::HidApi, dev_path: &CString) -> Result<Device> {
+    let path = dev_path.to_string_lossy();
+    devices::check_access(&path)?;
 
-        let mut default_data = [0u8; 48];
-        default_data[0] = 0x08;
+    // Devices that hidapi lists have libusb paths. hidraw nodes belong to
+    // virtual devices (see `devices::virtual_devices`), which hidapi does not know.
+    let res = if devices::is_hidraw_path(&path) {
+        Hidraw::open(&path).map(Device::Hidraw).ok()
+    }
+    else {
+        api.open_path(dev_path).map(Device::Hid).ok()
+    };
+
+    // hidapi does not tell why opening failed, and it might have checked another node than `check_access`
+    res.ok_or_else(|| FootswitchError::PermissionDenied(
+        format!("Could not open the device at {}. Make sure your device is connected and that you are allowed to access it. {}",
+                path, udev_rule::install_instructions())))
+}
 
-        // Initialize actual object
+impl<T: Transport> Pedals<T> {
+    /// Create a new object which communicates through an arbitrary transport
+    pub fn with_transport(dev: T) -> Pedals<T> {
         Pedals {
-            dev: dev,
-            start: start,
-
-            ped_data: vec![
-                PedalsData {
-                    header: header_0,
-                    data: default_data,
-                    length: 8,
-                },
-                PedalsData {
-                    header: header_1,
-                    data: default_data,
-                    length: 8,
-                },
-                PedalsData {
-                    header: header_2,
-                    data: default_data,
-                    length: 8, },
-            ]
+            dev,
+            start: protocol::start_packet(),
+            ped_data: vec![PedalConfig::Unconfigured; protocol::NUM_PEDALS],
+            ped_raw: vec![None; protocol::NUM_PEDALS],
         }
     }
 
-    pub fn read_pedal(&self, ped:& u8) -> [u8; 8] {
-        let mut buf = [0u8; 8];
-        let mut query = [0x01u8, 0x82, 0x08, 0x01, 0x00, 0x00, 0x00, 0x00];
+    /// Configuration of a pedal, as it will be written by `write_pedals`
+    pub fn config(&self, ped:usize) -> Result<&PedalConfig> {
+        check_pedal(ped)?;
+
+        Ok(&self.ped_data[ped])
+    }
+
+    /// Replace the configuration of a pedal. It is not written until `write_pedals` is called.
+    pub fn set_config(& mut self, ped:usize, config:PedalConfig) -> Result<()> {
+        check_pedal(ped)?;
+
+        if config.data_len() > MAX_STRING_LEN {
+            return Err(FootswitchError::StringTooLong { len: config.data_len(), max: MAX_STRING_LEN });
+        }
+
+        self.ped_data[ped] = config;
+        self.ped_raw[ped] = None;
+
+        Ok(())
+    }
 
-        query[3] += ped;
+    /// Write the given data bytes to a pedal, exactly as they are, even if
+    /// they can not be represented by a `PedalConfig`. The first byte is the
+    /// length of the data; additional bytes (e.g., padding) are dropped.
+    /// Blank pedals are written like unconfigured ones (see `PedalConfig::decode`).
+    pub fn set_raw(& mut self, ped:usize, data:&[u8]) -> Result<()> {
+        check_pedal(ped)?;
+
+        if let Ok(PedalConfig::Unconfigured) = PedalConfig::decode(data) {
+            return self.set_config(ped, PedalConfig::Unconfigured);
+        }
+
+        let len = data.first().cloned().unwrap_or(0) as usize;
+
+        if !(2..=protocol::MAX_DATA_LEN).contains(&len) || data.len() < len {
+            return Err(FootswitchError::Protocol(
+                format!("Invalid data for pedal {}: length {} with {} bytes of data.", ped, len, data.len())));
+        }
+
+        // Keep the typed configuration for data that it understands
+        self.ped_data[ped] = PedalConfig::decode(&data[..len]).unwrap_or_default();
+        self.ped_raw[ped] = Some(data[..len].to_vec());
+
+        Ok(())
+    }
+
+    pub fn read_pedal(&self, ped:& u8) -> Result<[u8; 8]> {
+        check_pedal(*ped as usize)?;
+
+        let mut buf = [0u8; 8];
+        let query = protocol::query_packet(*ped);
 
         // Write query to device
-        self.dev.write(&query).unwrap();
+        self.dev.write(&query)?;
 
         // Read answer
-        self.dev.read(&mut buf[..]).unwrap();
+        self.dev.read(&mut buf[..])?;
 
-        buf
+        Ok(buf)
     }
 
-    /// Read the current values of the pedals
-    pub fn read_pedals(&self, peds: Vec<u8>) {
-        let total_width = 55 as usize;
+    /// Read all data bytes of a pedal, exactly as the device returns them.
+    /// Data that is longer than a packet (i.e., strings) spans several packets,
+    /// so the result is always a multiple of 8 bytes long.
+    pub fn read_raw(&self, ped:u8) -> Result<Vec<u8>> {
+        let mut data = self.read_pedal(&ped)?.to_vec();
 
-        // Check if passed pedal number is valid
-        for i in peds.iter() {
-            if *i > 2 {
-                error!("Pedal value {} is larger than 2 and thus not valid!", i);
-            }
+        let len = data[0] as usize;
+
+        if len > protocol::MAX_DATA_LEN {
+            return Err(FootswitchError::Protocol(format!("The pedal returned an invalid data length ({}).", len)));
         }
 
-        // Print header
-        println!("├{}┐", "─".repeat(total_width));
-        println!("│{name:^width$}│", name = "Programmed Keys", width = total_width);
-        println!("╞{}╡", "═".repeat(total_width));
+        while data.len() < len {
+            let mut buf = [0u8; 8];
+            self.dev.read(&mut buf[..])?;
 
-        // Read and print keys
-        for (i, ped) in peds.iter().enumerate() {
-            // Read value from pedal and directly translate it to a key
-            let mut key_value = self.read_pedal(ped);
+            data.extend_from_slice(&buf);
+        }
 
-            let key_name_option = match Type::u8_to_enum(key_value[1]) {
-                Some(Type::Unconfigured) => None,
-                Some(Type::Key) => key_operations::print_key(&key_value),
-                Some(Type::Mouse) => key_operations::print_mousebutton(&key_value),
-                Some(Type::MouseKey) => key_operations::print_mouse_key(&key_value),
-                Some(Type::String) => self.print_string(& mut key_value),
-                None => error!("The key type which was returned by the pedal was invalid!")
-            };
+        Ok(data)
+    }
 
-            let key_name = match key_name_option {
-                Some(key) => key,
-                None => "< None >".to_string(),
-            };
+    /// Read the current configuration of a pedal
+    pub fn read_config(&self, ped:u8) -> Result<PedalConfig> {
+        PedalConfig::decode(&self.read_raw(ped)?)
+    }
 
-            println!("│  Pedal {ped}  │  {name:<-width$}│", ped = ped, name = key_name, width = total_width - 14);
+    /// Read the current value of a pedal and translate it to a readable
+    /// description. Returns `None` if the pedal is not configured.
+    pub fn describe_pedal(&self, ped: u8) -> Result<Option<String>> {
+        // Read value from pedal and directly translate it to a key
+        let mut key_value = self.read_pedal(&ped)?;
+
+        match Type::u8_to_enum(key_value[1]) {
+            Some(Type::Unconfigured) => Ok(None),
+            Some(Type::Key) => Ok(key_operations::print_key(&key_value)),
+            Some(Type::Mouse) => Ok(key_operations::print_mousebutton(&key_value)),
+            Some(Type::MouseKey) => Ok(key_operations::print_mouse_key(&key_value)),
+            Some(Type::String) => self.print_string(& mut key_value),
+            None => Err(invalid_type(key_value[1])),
+        }
+    }
 
-            // Print spacer between lines
-            if i != peds.len() - 1 {
-                println!("│ {}┼{name:<-width$}│", "─".repeat(10), name = "─".repeat(total_width - 14), width = total_width - 12);
-            }
+    /// Data bytes that `write_pedals` writes to a pedal
+    fn pedal_bytes(&self, ped:usize) -> Vec<u8> {
+        match self.ped_raw[ped] {
+            Some(ref raw) => raw.clone(),
+            None => self.ped_data[ped].encode(),
         }
+    }
+
+    /// Data bytes of the given pedals, as they will be written
+    fn staged(&self, peds:&[usize]) -> Result<Vec<(usize, Vec<u8>)>> {
+        peds.iter().map(|ped| {
+            check_pedal(*ped)?;
+
+            Ok((*ped, self.pedal_bytes(*ped)))
+        }).collect()
+    }
 
-        // Print simple footer
-        println!("├{}┘", "─".repeat(total_width));
+    /// All packets that `write_only` sends to the device, without sending them
+    pub fn packets(&self, peds:&[usize]) -> Result<Vec<Vec<u8>>> {
+        Ok(self.data_packets(&self.staged(peds)?))
     }
 
-    /// Sets the type of the function. False (0) if everything went fine, true (1) if
-    /// an error occurred.
-    fn set_type(& mut self, ped:usize, typ:Type) {
-        let set_value = if self.ped_data[ped].data[1] == 0 { true } else { false };
+    fn data_packets(&self, data:&[(usize, Vec<u8>)]) -> Vec<Vec<u8>> {
+        let mut packets = vec![self.start.to_vec()];
 
-        if set_value {
-            self.ped_data[ped].data[1] = typ as u8;
+        for (ped, bytes) in data.iter() {
+            packets.extend(pedal_packets(*ped, bytes));
         }
 
-        let ret = match typ {
-            Type::String => {
-                // If nothing is set, set type to string and length to 2
-                if set_value {
-                    self.ped_data[ped].length = 2;
-                }
+        packets
+    }
 
-                // Check if pedal type is set to String, otherwise error
-                self.ped_data[ped].data[1] != Type::String as u8
-            }
-            _ => {
-                let ret;
-
-                if self.ped_data[ped].data[1] == Type::String as u8 {
-                    // if new type is Key or Mouse, and String is already set, return false
-                    ret = true;
-                }
-                else {
-                    // else, set type to new type and return true
-                    self.ped_data[ped].data[1] |= typ as u8;
-                    ret = false;
-                }
-
-                ret
-            }
-        };
+    /// This method writes all data from Pedals.peddata to the device
+    pub fn write_pedals(&self) -> Result<()> {
+        self.write_only(&[0, 1, 2])
+    }
+
+    /// Write only the given pedals to the device. The other pedals keep their value.
+    pub fn write_only(&self, peds:&[usize]) -> Result<()> {
+        self.write_data(&self.staged(peds)?)
+    }
 
-        if ret {
-            error!("Invalid combination of options! Please see https://git.dennispotter.eu/Dennis/footswitch-rs/wiki");
+    fn write_data(&self, data:&[(usize, Vec<u8>)]) -> Result<()> {
+        for packet in self.data_packets(data).iter() {
+            self.dev.write(packet)?;
         }
+
+        Ok(())
     }
 
-    fn write_pedal(&self, ped:usize) {
-        // First, write header
-        self.dev.write(&self.ped_data[ped].header).unwrap();
+    /// Read the given pedals back from the device and check that they contain
+    /// what was written. All pedals that differ are reported.
+    pub fn verify(&self, peds:&[usize]) -> Result<()> {
+        self.verify_data(&self.staged(peds)?)
+    }
 
-        // Write data to device in 8 byte chunks
-        let mut up:usize = 0;
+    fn verify_data(&self, data:&[(usize, Vec<u8>)]) -> Result<()> {
+        let mut mismatches = Vec::new();
 
-        for i in 0..(self.ped_data[ped].length / 8) {
-            // Set bounds
-            let low = (i * 8) as usize;
-            up  = 8 * (i + 1) as usize;
+        for (ped, expected) in data.iter() {
+            let mut actual = self.read_raw(*ped as u8)?;
 
-            // Write to device
-            self.dev.write(&self.ped_data[ped].data[low..up]).unwrap();
+            // Strip the padding of the last packet
+            if actual.len() > expected.len() && actual[0] == expected[0] {
+                actual.truncate(expected.len());
+            }
+
+            if actual != *expected {
+                mismatches.push(Mismatch { pedal: *ped, expected: expected.clone(), actual });
+            }
         }
 
-        // Write remaining values to device
-        if self.ped_data[ped].length % 8 > 0 {
-            self.dev.write(&self.ped_data[ped].data[up..(self.ped_data[ped].length as usize)]).unwrap();
+        if !mismatches.is_empty() {
+            return Err(FootswitchError::VerificationFailed(mismatches));
         }
+
+        Ok(())
     }
 
-    /// This method writes all data from Pedals.peddata to the device
-    pub fn write_pedals(&self) {
-        self.dev.write(&self.start).unwrap();
+    /// Write the given pedals and verify them. Pedals that do not match are
+    /// written again, at most `retries` times.
+    pub fn write_verified(&self, peds:&[usize], retries:usize) -> Result<()> {
+        self.write_verified_data(&self.staged(peds)?, retries)
+    }
+
+    fn write_verified_data(&self, data:&[(usize, Vec<u8>)], retries:usize) -> Result<()> {
+        let mut pending = data.to_vec();
+        let mut attempt = 0;
 
-        for (i, _pedal) in self.ped_data.iter().enumerate() {
-            self.write_pedal(i)
+        loop {
+            self.write_data(&pending)?;
+
+            match self.verify_data(&pending) {
+                Err(FootswitchError::VerificationFailed(ref mismatches)) if attempt < retries => {
+                    pending.retain(|(ped, _)| mismatches.iter().any(|mismatch| mismatch.pedal == *ped));
+                    attempt += 1;
+                },
+                result => return result,
+            }
         }
     }
 
-    pub fn set_key(& mut self, ped:usize, key:&str) {
-        if let Some(encoded_key) = key_operations::encode_byte(key) {
-            self.set_type(ped, Type::Key);
+    /// Read the memory of all pedals, exactly as the device returns it
+    pub fn backup(&self) -> Result<Backup> {
+        let pedals = (0..protocol::NUM_PEDALS).map(|ped| self.read_raw(ped as u8)).collect::<Result<Vec<_>>>()?;
 
-            self.ped_data[ped].data[3] = encoded_key;
-        }
-        else {
-            error!("Key '{}' is not recognized! Please provide a valid key, listed in './footswitch-rs --listkeys 4'", key);
+        Ok(Backup { pedals })
+    }
+
+    /// Stage the data of a backup for the next write (see `set_raw`)
+    pub fn set_backup(& mut self, backup:&Backup) -> Result<()> {
+        for (ped, data) in backup.pedals.iter().enumerate() {
+            self.set_raw(ped, data)?;
         }
+
+        Ok(())
     }
 
-    pub fn append_key(& mut self, ped:usize, key:&str) {
-        if let Some(encoded_key) = key_operations::encode_byte(key) {
-            self.set_type(ped, Type::String);
+    /// Read the memory of all pedals, without the padding of the last packet.
+    /// The result can be written back with `write_transaction`.
+    pub fn snapshot(&self) -> Result<Backup> {
+        let pedals = (0..protocol::NUM_PEDALS).map(|ped| {
+            let mut data = self.read_raw(ped as u8)?;
 
-            let mut key = Vec::new();
-            key.push(encoded_key);
+            // Blank pedals are stored like they are written (see `PedalConfig::decode`)
+            if let Ok(PedalConfig::Unconfigured) = PedalConfig::decode(&data) {
+                return Ok(PedalConfig::Unconfigured.encode());
+            }
 
-            self.compile_string_data(ped,key);
+            data.truncate(data[0] as usize);
+            Ok(data)
+        }).collect::<Result<Vec<_>>>()?;
+
+        Ok(Backup { pedals })
+    }
+
+    /// Write and verify the given pedals like `write_verified`, but take a
+    /// snapshot first. If writing or verifying fails, the snapshot is written
+    /// back, so that the device is not left partially updated.
+    pub fn write_transaction(&self, peds:&[usize], retries:usize) -> Result<()> {
+        let snapshot = self.snapshot()?;
+
+        self.write_or_restore(peds, retries, &snapshot)
+    }
+
+    /// Write and verify the given pedals, or restore them from `snapshot` if
+    /// that fails. The returned error tells whether restoring succeeded.
+    pub fn write_or_restore(&self, peds:&[usize], retries:usize, snapshot:&Backup) -> Result<()> {
+        let cause = match self.write_verified(peds, retries) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+
+        let previous = peds.iter().map(|ped| {
+            let data = snapshot.pedals.get(*ped).cloned().ok_or(FootswitchError::InvalidPedal(*ped))?;
+
+            Ok((*ped, data))
+        }).collect::<Result<Vec<_>>>()?;
+
+        let rollback = match self.write_verified_data(&previous, retries) {
+            Ok(()) => Rollback::Restored,
+            Err(err) => Rollback::Failed(Box::new(err)),
+        };
+
+        Err(FootswitchError::WriteFailed {
+            cause: Box::new(cause),
+            pedals: peds.to_vec(),
+            rollback,
+            snapshot: snapshot.clone(),
+        })
+    }
+
+    /// Write the given configuration of all pedals, but only to pedals whose
+    /// current value differs, and verify the result. If that fails, the
+    /// previous values are restored. Returns the pedals that were written,
+    /// which is empty if the device already matched.
+    pub fn apply(& mut self, configs:&[PedalConfig], retries:usize) -> Result<Vec<usize>> {
+        if configs.len() != protocol::NUM_PEDALS {
+            return Err(FootswitchError::InvalidProfile(
+                format!("Expected {} pedal configurations, got {}.", protocol::NUM_PEDALS, configs.len())));
         }
-        else {
-            error!("Key '{}' is not recognized! Please provide a valid key, listed in './footswitch-rs --listkeys 4'", key);
+
+        let snapshot = self.snapshot()?;
+        let mut changed = Vec::new();
+
+        for (ped, config) in configs.iter().enumerate() {
+            let current = PedalConfig::decode(&snapshot.pedals[ped])?;
+
+            if current.encode() != config.encode() {
+                changed.push(ped);
+            }
+
+            self.set_config(ped, if changed.contains(&ped) { config.clone() } else { current })?;
+        }
+
+        if !changed.is_empty() {
+            self.write_or_restore(&changed, retries, &snapshot)?;
         }
+
+        Ok(changed)
+    }
+
+    pub fn set_key(& mut self, ped:usize, key:&str) -> Result<()> {
+        let encoded_key = encode_key(key)?;
+
+        self.pedal_mut(ped)?.set_key(encoded_key)
     }
 
-    pub fn set_modifier(& mut self, ped:usize, modifier:&str) {
+    pub fn append_key(& mut self, ped:usize, key:&str) -> Result<()> {
+        let encoded_key = encode_key(key)?;
+
+        self.pedal_mut(ped)?.append_keys(&[encoded_key])
+    }
+
+    pub fn set_modifier(& mut self, ped:usize, modifier:&str) -> Result<()> {
         let modifier = match key_operations::Modifier::str_to_enum(modifier) {
             Some(x) => x,
-            None => error!("Unknown modifier! Please use one of the following: ctrl, shift, alt, win."),
+            None => return Err(FootswitchError::InvalidKey(
+                format!("Unknown modifier '{}'! Please use one of the following: ctrl, shift, alt, win.", modifier))),
         };
 
-        self.set_type(ped, Type::Key);
-
-        self.ped_data[ped].data[2] |= modifier as u8;
+        self.pedal_mut(ped)?.add_modifier(modifier)
     }
 
-    pub fn set_mousebutton(& mut self, ped:usize, mousebutton:&str) {
+    pub fn set_mousebutton(& mut self, ped:usize, mousebutton:&str) -> Result<()> {
         let mousebutton = match key_operations::MouseButton::str_to_enum(mousebutton) {
             Some(x) => x,
-            None => error!("Unknown mousebutton! Please use one of the following: left, middle, right, double."),
+            None => return Err(FootswitchError::InvalidKey(
+                format!("Unknown mousebutton '{}'! Please use one of the following: left, middle, right, double.", mousebutton))),
         };
 
-        self.set_type(ped, Type::Mouse);
-
-        self.ped_data[ped].data[4] |= mousebutton as u8;
+        self.pedal_mut(ped)?.add_mousebutton(mousebutton)
     }
 
-    pub fn set_mouse_xyw(& mut self, ped:usize, value_i8:i8, direction:usize) {
-        // The values of the directions match the array index of ped_data[].data[]
+    pub fn set_mouse_xyw(& mut self, ped:usize, value:i8, direction:usize) -> Result<()> {
+        // The values of the directions match the index of the byte in the data of the pedal
         // X = 5
         // Y = 6
         // W = 7
+        let pedal = self.pedal_mut(ped)?;
 
-        // Translate to u8
-        let mut value_u8 = value_i8 as u8;
-
-        // Check if mouse wheel movement is smaller than 0, if so, add 256
-        if (direction == 7) & (value_i8 < 0) {
-            value_u8 += value_i8 as u8 + 256;
+        match direction {
+            5 => pedal.set_movement(Some(value), None, None),
+            6 => pedal.set_movement(None, Some(value), None),
+            7 => pedal.set_movement(None, None, Some(value)),
+            _ => Err(FootswitchError::InvalidKey(format!("Invalid mouse direction {}! Please use 5 (X), 6 (Y), or 7 (W).", direction))),
         }
-
-        // Set Mouse Type
-        self.set_type(ped, Type::Mouse);
-
-        // Actually write data
-        self.ped_data[ped].data[direction] = value_u8;
     }
 
-    pub fn print_string(&self, response: & mut [u8]) -> Option<String> {
+    pub fn print_string(&self, response: & mut [u8]) -> Result<Option<String>> {
+        if response[0] < 2 {
+            return Err(FootswitchError::Protocol(format!("The pedal returned an invalid string length ({}).", response[0])));
+        }
+
         let mut string = String::new();
         let mut len = response[0] - 2;
         let mut ind = 2;
@@ -329,7 +560,7 @@ impl Pedals {
         while len > 0 {
 
             if ind == 8 {
-                self.dev.read(&mut response[..]).unwrap();
+                self.dev.read(&mut response[..])?;
 
                 ind = 0;
             }
@@ -342,100 +573,459 @@ impl Pedals {
             ind += 1;
         }
 
-        Some(string)
+        Ok(Some(string))
     }
 
 
-    pub fn set_string(& mut self, ped:usize, key:&str) {
-            self.set_type(ped, Type::String);
-
-            if key.len() > 38 {
-                error!("The size of each string must be smaller than or equal to 38.");
+    pub fn set_string(& mut self, ped:usize, key:&str) -> Result<()> {
+            if key.len() > MAX_STRING_LEN {
+                return Err(FootswitchError::StringTooLong { len: key.len(), max: MAX_STRING_LEN });
             }
 
-            let encoded_vector = match key_operations::encode_string(&key) {
+            let encoded_vector = match key_operations::encode_string(key) {
                 Some(x) => x,
-                None => error!("Could not encode string!"),
+                None => return Err(FootswitchError::InvalidKey(
+                    format!("Could not encode string '{}'! It contains characters that are not listed in './footswitch-rs list -c 4'", key))),
             };
 
-            self.compile_string_data(ped, encoded_vector);
+            self.pedal_mut(ped)?.append_keys(&encoded_vector)
     }
 
-    fn compile_string_data(& mut self, ped:usize, enc_vec:Vec<u8>) {
-        let len = enc_vec.len() as u8;
+    fn pedal_mut(& mut self, ped:usize) -> Result<&mut PedalConfig> {
+        check_pedal(ped)?;
 
-        if self.ped_data[ped].length + len > 38 {
-            error!("The size of the accumulated string must be smaller than or equal to 38.")
+        // Changing the configuration replaces the raw data
+        self.ped_raw[ped] = None;
+
+        Ok(&mut self.ped_data[ped])
+    }
+
+    /// Prevent the application from purging pedals that are not explicitly set
+    pub fn refresh_values(& mut self, peds: Vec<u8>) -> Result<()> {
+
+        // First read from pedals that are defined in peds
+        for ped in peds.iter() {
+            // Read all packets, so that strings which span several packets are kept completely
+            let data = self.read_raw(*ped)?;
+
+            // Make sure that the pedal can be modified, e.g., by `append`. Write
+            // back exactly what was read, unless the pedal is modified or blank.
+            match PedalConfig::decode(&data)? {
+                PedalConfig::Unconfigured => self.set_config(*ped as usize, PedalConfig::Unconfigured)?,
+                _ => self.set_raw(*ped as usize, &data)?,
+            }
         }
 
-        let start_byte = self.ped_data[ped].length as usize;
-        for (i, c) in enc_vec.iter().enumerate() {
-            self.ped_data[ped].data[start_byte + i] = *c;
+        Ok(())
+    }
+}
+
+
+/// Packets that write data to a pedal: the header, followed by the data in 8
+/// byte chunks. The last chunk may be shorter.
+fn pedal_packets(ped:usize, data:&[u8]) -> Vec<Vec<u8>> {
+    let mut packets = vec![protocol::header_packet(ped as u8, data.len() as u8).to_vec()];
+    packets.extend(data.chunks(protocol::PACKET_SIZE).map(|chunk| chunk.to_vec()));
+
+    packets
+}
+
+fn check_pedal(ped: usize) -> Result<()> {
+    if ped >= protocol::NUM_PEDALS {
+        return Err(FootswitchError::InvalidPedal(ped));
+    }
+
+    Ok(())
+}
+
+fn encode_key(key: &str) -> Result<u8> {
+    match key_operations::encode_byte(key) {
+        Some(encoded_key) => Ok(encoded_key),
+        None => Err(FootswitchError::InvalidKey(
+            format!("Key '{}' is not recognized! Please provide a valid key, listed in './footswitch-rs list -c 4'", key))),
+    }
+}
+
+fn invalid_type(typ: u8) -> FootswitchError {
+    FootswitchError::Protocol(format!("The key type which was returned by the pedal ({:#04x}) was invalid!", typ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transport::MockTransport;
+    use error::EXIT_ROLLBACK_FAILED;
+
+    const KEY_A: [u8; 8] = [0x08, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00];
+    const KEY_B: [u8; 8] = [0x08, 0x01, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn write_pedals_sends_start_headers_and_chunks() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+        pedals.set_key(0, "a").unwrap();
+        pedals.set_modifier(0, "ctrl").unwrap();
+        pedals.set_string(1, "hello world").unwrap();
+        pedals.set_mousebutton(2, "left").unwrap();
+        pedals.set_mouse_xyw(2, -1, 6).unwrap();
+
+        pedals.write_pedals().unwrap();
+
+        assert_eq!(pedals.dev.written(), vec![
+            vec![0x01, 0x80, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00],
+            vec![0x01, 0x81, 0x08, 0x01, 0x00, 0x00, 0x00, 0x00],
+            vec![0x08, 0x01, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00],
+            vec![0x01, 0x81, 0x0d, 0x02, 0x00, 0x00, 0x00, 0x00],
+            vec![0x0d, 0x04, 0x0b, 0x08, 0x0f, 0x0f, 0x12, 0x2c],
+            vec![0x1a, 0x12, 0x15, 0x0f, 0x07],
+            vec![0x01, 0x81, 0x08, 0x03, 0x00, 0x00, 0x00, 0x00],
+            vec![0x08, 0x02, 0x00, 0x00, 0x01, 0x00, 0xff, 0x00],
+        ]);
+    }
+
+    #[test]
+    fn write_only_leaves_other_pedals_alone() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+        pedals.set_key(2, "b").unwrap();
+
+        pedals.write_only(&[2]).unwrap();
+
+        assert_eq!(pedals.dev.written(), vec![
+            protocol::start_packet().to_vec(), protocol::header_packet(2, 8).to_vec(), KEY_B.to_vec(),
+        ]);
+    }
+
+    #[test]
+    fn append_extends_refreshed_pedals() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+
+        // Pedal 0 types "ab", pedal 1 sends <a>
+        pedals.dev.push_reply(&[0x04, 0x04, 0x04, 0x05, 0x00, 0x00, 0x00, 0x00]);
+        pedals.dev.push_reply(&KEY_A);
+        pedals.refresh_values(vec![0, 1]).unwrap();
+
+        assert_eq!(pedals.dev.written(), vec![protocol::query_packet(0).to_vec(), protocol::query_packet(1).to_vec()]);
+
+        pedals.append_key(0, "c").unwrap();
+        pedals.set_modifier(1, "shift").unwrap();
+
+        assert_eq!(*pedals.config(0).unwrap(), PedalConfig::String { keys: vec![0x04, 0x05, 0x06] });
+        assert_eq!(*pedals.config(1).unwrap(), PedalConfig::Key { modifiers: 0x02, key: 0x04 });
+
+        // A key can not be appended to a pedal that sends a key
+        assert!(pedals.append_key(1, "d").is_err());
+    }
+
+    #[test]
+    fn reads_and_describes_scripted_pedals() {
+        let pedals = Pedals::with_transport(MockTransport::new());
+
+        pedals.dev.push_reply(&[0x08, 0x01, 0x05, 0x4c, 0x00, 0x00, 0x00, 0x00]);
+        pedals.dev.push_reply(&[0x08, 0x02, 0x00, 0x00, 0x01, 0xfb, 0x00, 0x00]);
+        pedals.dev.push_reply(&[0x0d, 0x04, 0x0b, 0x08, 0x0f, 0x0f, 0x12, 0x2c]);
+        pedals.dev.push_reply(&[0x1a, 0x12, 0x15, 0x0f, 0x07, 0x00, 0x00, 0x00]);
+        pedals.dev.push_reply(&protocol::unconfigured_data());
+
+        assert_eq!(pedals.describe_pedal(0).unwrap(), Some("ctrl+alt+<delete>".to_string()));
+        assert_eq!(pedals.describe_pedal(1).unwrap(), Some("left X = -5 Y = 0 W = 0".to_string()));
+        assert_eq!(pedals.describe_pedal(2).unwrap(), Some("<h><e><l><l><o>< ><w><o><r><l><d>".to_string()));
+        assert_eq!(pedals.describe_pedal(0).unwrap(), None);
+
+        assert_eq!(pedals.dev.pending_replies(), 0);
+        assert_eq!(pedals.dev.written(), vec![
+            protocol::query_packet(0).to_vec(), protocol::query_packet(1).to_vec(),
+            protocol::query_packet(2).to_vec(), protocol::query_packet(0).to_vec(),
+        ]);
+
+        pedals.dev.push_reply(&[0x08, 0x03, 0x02, 0x05, 0x01, 0x0a, 0xf6, 0x00]);
+
+        assert_eq!(pedals.read_config(1).unwrap(),
+                   PedalConfig::MouseKey { modifiers: 0x02, key: 0x05, buttons: 0x01, x: 10, y: -10, w: 0 });
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+
+        assert!(pedals.set_key(3, "a").is_err());
+        assert!(pedals.set_key(0, "notakey").is_err());
+        assert!(pedals.set_modifier(0, "hyper").is_err());
+        assert!(pedals.set_mousebutton(0, "fourth").is_err());
+        assert!(pedals.set_string(0, &"a".repeat(MAX_STRING_LEN + 1)).is_err());
+        assert!(pedals.dev.written().is_empty());
+    }
+
+    #[test]
+    fn verify_reports_mismatch() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+        pedals.set_key(0, "a").unwrap();
+
+        pedals.dev.push_reply(&KEY_B);
+
+        match pedals.write_verified(&[0], 0) {
+            Err(FootswitchError::VerificationFailed(mismatches)) => {
+                assert_eq!(mismatches, vec![Mismatch { pedal: 0, expected: KEY_A.to_vec(), actual: KEY_B.to_vec() }]);
+            },
+            _ => panic!("Verification should fail"),
         }
+    }
+
+    #[test]
+    fn write_verified_retries_mismatching_pedals() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+        pedals.set_key(0, "a").unwrap();
 
-        self.ped_data[ped].length += len;
-        self.ped_data[ped].header[2] = self.ped_data[ped].length;
-        self.ped_data[ped].data[0] = self.ped_data[ped].length;
+        pedals.dev.push_reply(&KEY_B);
+        pedals.dev.push_reply(&KEY_A);
 
+        pedals.write_verified(&[0], 1).unwrap();
 
+        let start = protocol::start_packet().to_vec();
+        let header = protocol::header_packet(0, 8).to_vec();
+        let query = protocol::query_packet(0).to_vec();
+
+        assert_eq!(pedals.dev.written(), vec![
+            start.clone(), header.clone(), KEY_A.to_vec(), query.clone(),
+            start, header, KEY_A.to_vec(), query,
+        ]);
     }
 
-    /// Update device and close application
-    pub fn update_and_close(& mut self) {
-        self.write_pedals();
+    #[test]
+    fn write_transaction_restores_snapshot_on_failure() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+        pedals.set_key(0, "a").unwrap();
+
+        // Snapshot, verification of the write, and verification of the restored snapshot
+        for reply in [KEY_B, KEY_B, KEY_B, KEY_B, KEY_B].iter() {
+            pedals.dev.push_reply(reply);
+        }
 
-        info!("Successfully wrote everything to footpedal!");
-        info!("The current state of the device is shown below.");
+        match pedals.write_transaction(&[0], 0) {
+            Err(FootswitchError::WriteFailed { pedals: ref written, rollback: Rollback::Restored, .. }) => assert_eq!(*written, vec![0]),
+            _ => panic!("Writing should fail and be rolled back"),
+        }
 
-        // Show user current state of pedal
-        self.read_pedals(vec![0,1,2]);
+        let start = protocol::start_packet().to_vec();
+        let header = protocol::header_packet(0, 8).to_vec();
+        let query = protocol::query_packet(0).to_vec();
 
-        goodbye();
+        assert_eq!(pedals.dev.written()[3..].to_vec(), vec![
+            start.clone(), header.clone(), KEY_A.to_vec(), query.clone(),
+            start, header, KEY_B.to_vec(), query,
+        ]);
     }
 
-    /// Prevent the application from purging pedals that are not explicitly set
-    pub fn refresh_values(& mut self, peds: Vec<u8>) {
+    #[test]
+    fn write_transaction_reports_failed_rollback() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+        pedals.set_key(0, "a").unwrap();
 
-        // First read from pedals that are defined in peds
-        for ped in peds.iter() {
-            // Read value from pedal and directly translate it to a key
-            let mut key_value = self.read_pedal(ped);
+        // The device stops answering after the write
+        for reply in [KEY_B, KEY_B, KEY_B].iter() {
+            pedals.dev.push_reply(reply);
+        }
 
-            match Type::u8_to_enum(key_value[1]) {
-                Some(Type::Key) => {
-                    self.set_type(*ped as usize, Type::Key);
-                    // Modifiers
-                    self.ped_data[*ped as usize].data[2] = key_value[2];
+        match pedals.write_transaction(&[0], 0) {
+            Err(ref err @ FootswitchError::WriteFailed { rollback: Rollback::Failed(_), ref snapshot, .. }) => {
+                assert_eq!(snapshot.pedals, vec![KEY_B.to_vec(); 3]);
+                assert_eq!(err.exit_code(), EXIT_ROLLBACK_FAILED);
+            },
+            _ => panic!("Restoring the snapshot should fail"),
+        }
+    }
 
-                    // Keys
-                    self.ped_data[*ped as usize].data[3] = key_value[3];
-                },
-                Some(Type::Mouse) => {
-                    self.set_type(*ped as usize, Type::Mouse);
-                    self.ped_data[*ped as usize].data[4] = key_value[4];
-                },
-                Some(Type::MouseKey) => {
-                    self.set_type(*ped as usize, Type::MouseKey);
-                    self.ped_data[*ped as usize].data[3] = key_value[3];
-                    self.ped_data[*ped as usize].data[4] = key_value[4];
-                },
-                Some(Type::String) => {
-                    self.set_type(*ped as usize, Type::String);
-
-                    // Start byte should be 2
-                    let mut key_vec = Vec::new();
-                    for (i, c) in key_value.iter().enumerate() {
-                        if i >= 2 && *c != 0 {
-                            key_vec.push(*c);
-                        }
-                    }
-
-                    self.compile_string_data(*ped as usize, key_vec);
-                },
+    fn key_configs(keys: &[[u8; 8]; 3]) -> Vec<PedalConfig> {
+        keys.iter().map(|key| PedalConfig::decode(key).unwrap()).collect()
+    }
 
-                None => error!("The key type which was returned by the pedal was invalid!"),
-                _ => {}
-            };
+    #[test]
+    fn apply_skips_unchanged_pedals() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+
+        for reply in [KEY_A, KEY_A, KEY_B].iter() {
+            pedals.dev.push_reply(reply);
+        }
+
+        assert_eq!(pedals.apply(&key_configs(&[KEY_A, KEY_A, KEY_B]), 0).unwrap(), Vec::<usize>::new());
+
+        // Only the snapshot was read
+        assert_eq!(pedals.dev.written(), (0..3).map(|ped| protocol::query_packet(ped).to_vec()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn apply_writes_only_changed_pedals() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+
+        for reply in [KEY_A, KEY_A, KEY_A, KEY_B].iter() {
+            pedals.dev.push_reply(reply);
+        }
+
+        assert_eq!(pedals.apply(&key_configs(&[KEY_A, KEY_B, KEY_A]), 0).unwrap(), vec![1]);
+
+        assert_eq!(pedals.dev.written()[3..].to_vec(), vec![
+            protocol::start_packet().to_vec(), protocol::header_packet(1, 8).to_vec(), KEY_B.to_vec(),
+            protocol::query_packet(1).to_vec(),
+        ]);
+    }
+
+    #[test]
+    fn apply_needs_all_pedals() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+
+        match pedals.apply(&vec![PedalConfig::Unconfigured; 2], 0) {
+            Err(FootswitchError::InvalidProfile(ref msg)) => assert_eq!(msg, "Expected 3 pedal configurations, got 2."),
+            _ => panic!("Two configurations should be rejected"),
+        }
+
+        assert!(pedals.dev.written().is_empty());
+    }
+
+    #[test]
+    fn apply_retries_mismatching_pedals() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+
+        // Snapshot, a failed verification, and a successful one
+        for reply in [KEY_A, KEY_A, KEY_A, KEY_A, KEY_B].iter() {
+            pedals.dev.push_reply(reply);
+        }
+
+        assert_eq!(pedals.apply(&key_configs(&[KEY_A, KEY_B, KEY_A]), 1).unwrap(), vec![1]);
+
+        let write = vec![
+            protocol::start_packet().to_vec(), protocol::header_packet(1, 8).to_vec(), KEY_B.to_vec(),
+            protocol::query_packet(1).to_vec(),
+        ];
+
+        assert_eq!(pedals.dev.written()[3..].to_vec(), [write.clone(), write].concat());
+        assert_eq!(pedals.dev.pending_replies(), 0);
+    }
+
+    #[test]
+    fn refresh_values_keeps_long_strings() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+
+        // "hello world" spans two packets
+        let string = [0x0d, 0x04, 0x0b, 0x08, 0x0f, 0x0f, 0x12, 0x2c, 0x1a, 0x12, 0x15, 0x0f, 0x07];
+        pedals.dev.push_reply(&string[..8]);
+        pedals.dev.push_reply(&[0x1a, 0x12, 0x15, 0x0f, 0x07, 0x00, 0x00, 0x00]);
+
+        pedals.refresh_values(vec![1]).unwrap();
+        pedals.set_key(0, "a").unwrap();
+
+        assert_eq!(pedals.dev.pending_replies(), 0);
+        assert_eq!(*pedals.config(1).unwrap(), PedalConfig::String { keys: string[2..].to_vec() });
+
+        assert_eq!(pedals.packets(&[0, 1]).unwrap(), vec![
+            protocol::start_packet().to_vec(),
+            protocol::header_packet(0, 8).to_vec(), KEY_A.to_vec(),
+            protocol::header_packet(1, 13).to_vec(), string[..8].to_vec(), string[8..].to_vec(),
+        ]);
+    }
+
+    #[test]
+    fn backup_and_restore_blank_and_raw_pedals() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+        let raw = [0x08, 0x81, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00];
+
+        pedals.dev.push_reply(&[0x00; 8]);
+        pedals.dev.push_reply(&raw);
+        pedals.dev.push_reply(&KEY_A);
+
+        let backup = Backup::from_text(&pedals.backup().unwrap().to_text()).unwrap();
+        assert_eq!(backup.pedals[0], vec![0x00; 8]);
+
+        pedals.set_backup(&backup).unwrap();
+        pedals.dev.clear_written();
+        pedals.write_pedals().unwrap();
+
+        assert_eq!(pedals.dev.written(), vec![
+            protocol::start_packet().to_vec(),
+            protocol::header_packet(0, 8).to_vec(), protocol::unconfigured_data().to_vec(),
+            protocol::header_packet(1, 8).to_vec(), raw.to_vec(),
+            protocol::header_packet(2, 8).to_vec(), KEY_A.to_vec(),
+        ]);
+    }
+
+    #[test]
+    fn blank_pedals_are_unconfigured() {
+        let mut pedals = Pedals::with_transport(MockTransport::new());
+        let blank = [0x00; 8];
+
+        pedals.dev.push_reply(&blank);
+        pedals.dev.push_reply(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        pedals.refresh_values(vec![0, 1]).unwrap();
+
+        assert_eq!(*pedals.config(0).unwrap(), PedalConfig::Unconfigured);
+        assert_eq!(pedals.packets(&[0, 1]).unwrap()[2], protocol::unconfigured_data().to_vec());
+
+        for _ in 0..3 {
+            pedals.dev.push_reply(&blank);
+        }
+
+        assert_eq!(pedals.snapshot().unwrap().pedals, vec![protocol::unconfigured_data().to_vec(); 3]);
+    }
+
+    fn device_info(path: &str, product_id: u16, interface_number: i32, serial: Option<&str>) -> hidapi::HidDeviceInfo {
+        hidapi::HidDeviceInfo {
+            path: CString::new(path).unwrap(),
+            vendor_id: 0x0c45,
+            product_id,
+            serial_number: serial.map(|serial| serial.to_string()),
+            release_number: 0,
+            manufacturer_string: None,
+            product_string: None,
+            usage_page: 0,
+            usage: 0,
+            interface_number,
         }
     }
+
+    fn select(devices: &[hidapi::HidDeviceInfo], selector: &DeviceSelector) -> Result<String> {
+        select_from(devices, selector, &devices::DeviceTable::default())
+            .map(|path| path.to_string_lossy().to_string())
+    }
+
+    #[test]
+    fn selects_configuration_interfaces() {
+        let devices = vec![
+            device_info("1-1:1.0", 0x7403, 0, Some("A1")),
+            device_info("1-1:1.1", 0x7403, 1, Some("A1")),
+            device_info("1-2:1.1", 0x7404, 1, Some("B2")),
+            device_info("1-3:1.1", 0x1234, 1, Some("C3")),
+        ];
+
+        assert!(matches!(select(&devices, &DeviceSelector::default()), Err(FootswitchError::MultipleDevices(ref found)) if found.len() == 2));
+
+        let by_serial = DeviceSelector { serial: Some("B2".to_string()), ..Default::default() };
+        assert_eq!(select(&devices, &by_serial).unwrap(), "1-2:1.1");
+
+        let by_path = DeviceSelector { path: Some("1-1:1.1".to_string()), ..Default::default() };
+        assert_eq!(select(&devices, &by_path).unwrap(), "1-1:1.1");
+
+        let by_index = DeviceSelector { index: Some(1), ..Default::default() };
+        assert_eq!(select(&devices, &by_index).unwrap(), "1-2:1.1");
+
+        let out_of_range = DeviceSelector { index: Some(2), ..Default::default() };
+        assert!(matches!(select(&devices, &out_of_range), Err(FootswitchError::DeviceNotFound(_))));
+
+        // The index counts only the devices that match the other criteria
+        let combined = DeviceSelector { serial: Some("A1".to_string()), index: Some(0), ..Default::default() };
+        assert_eq!(select(&devices, &combined).unwrap(), "1-1:1.1");
+
+        let unknown_serial = DeviceSelector { serial: Some("C3".to_string()), ..Default::default() };
+        assert!(matches!(select(&devices, &unknown_serial), Err(FootswitchError::DeviceNotFound(_))));
+
+        assert_eq!(select(&devices[..2], &DeviceSelector::default()).unwrap(), "1-1:1.1");
+        assert!(matches!(select(&[], &DeviceSelector::default()), Err(FootswitchError::DeviceNotFound(_))));
+    }
+
+    #[test]
+    fn falls_back_to_unenumerated_paths() {
+        let devices = vec![device_info("1-1:1.1", 0x7403, 1, None)];
+
+        let unenumerated = DeviceSelector { path: Some("/dev/hidraw7".to_string()), ..Default::default() };
+        assert_eq!(select(&devices, &unenumerated).unwrap(), "/dev/hidraw7");
+
+        let invalid = DeviceSelector { path: Some("/dev/hid\0raw".to_string()), ..Default::default() };
+        assert!(matches!(select(&devices, &invalid), Err(FootswitchError::DeviceNotFound(_))));
+    }
 }
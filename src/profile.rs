@@ -0,0 +1,720 @@
+//! Human-editable profiles of all pedals of a foot switch
+//!
+//! A profile describes every pedal by the names that are also used on the
+//! command line. Profiles can be stored as TOML or, if the file name ends with
+//! `.json`, as JSON:
+//!
+//! ```toml
+//! [[pedal]]
+//! pedal = 0
+//! key = "c"
+//! modifiers = ["ctrl"]
+//!
+//! [[pedal]]
+//! pedal = 1
+//! mouse_buttons = ["left"]
+//! x = 10
+//! y = -10
+//!
+//! [[pedal]]
+//! pedal = 2
+//! string = "hello"
+//! keys = ["enter"]
+//! ```
+//!
+//! Pedals that are not listed are unconfigured. Keys that have no name can be
+//! given by their code, e.g. `"0x32"`. The type of a pedal (`key`, `mouse`,
+//! `mousekey`, or `string`) follows from its settings; it is only given as
+//! `type` if the settings are empty, e.g., for a key without key and modifiers.
+//!
+//! Named profiles are stored as TOML files in `profiles_dir()`.
+
+use std::fs;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde_json;
+use toml;
+use key_operations;
+use key_operations::{Modifier, MouseButton};
+use pedal_config::PedalConfig;
+use protocol;
+use config;
+use error::{FootswitchError, Result};
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub pedal: Vec<PedalProfile>,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PedalProfile {
+    /// Number of the pedal: 0, 1, or 2
+    pub pedal: u8,
+
+    /// Type of the pedal, if it does not follow from the other settings
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modifiers: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mouse_buttons: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub x: i8,
+
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub y: i8,
+
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub w: i8,
+
+    /// Characters that are typed one after another
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub string: Option<String>,
+
+    /// Keys that are typed after `string`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+}
+
+fn is_zero(value: &i8) -> bool {
+    *value == 0
+}
+
+static MODIFIERS: &[(&str, u8)] = &[
+    ("ctrl", Modifier::Ctrl as u8),
+    ("shift", Modifier::Shift as u8),
+    ("alt", Modifier::Alt as u8),
+    ("win", Modifier::Win as u8),
+];
+
+static MOUSE_BUTTONS: &[(&str, u8)] = &[
+    ("left", MouseButton::MouseLeft as u8),
+    ("right", MouseButton::MouseRight as u8),
+    ("middle", MouseButton::MouseMiddle as u8),
+    ("double", MouseButton::MouseDouble as u8),
+];
+
+impl Profile {
+    /// Create a profile from the configuration of all pedals
+    pub fn from_configs(configs: &[PedalConfig]) -> Profile {
+        Profile {
+            pedal: configs.iter().enumerate().map(|(ped, config)| PedalProfile::from_config(ped as u8, config)).collect(),
+        }
+    }
+
+    /// Configuration of all pedals. Pedals that are not listed are unconfigured.
+    pub fn to_configs(&self) -> Result<Vec<PedalConfig>> {
+        let mut configs = vec![PedalConfig::Unconfigured; protocol::NUM_PEDALS];
+        let mut seen = [false; protocol::NUM_PEDALS];
+
+        for pedal in self.pedal.iter() {
+            let ped = pedal.pedal as usize;
+
+            if ped >= protocol::NUM_PEDALS {
+                return Err(FootswitchError::InvalidPedal(ped));
+            }
+
+            if seen[ped] {
+                return Err(FootswitchError::InvalidProfile(format!("Pedal {} is defined more than once.", ped)));
+            }
+
+            seen[ped] = true;
+            configs[ped] = match pedal.to_config() {
+                Err(FootswitchError::InvalidCombination) => return Err(FootswitchError::InvalidProfile(
+                    format!("Pedal {} combines a string with a key, modifiers, or a mouse action.", ped))),
+                config => config?,
+            };
+        }
+
+        Ok(configs)
+    }
+
+    /// Load a profile. Files that end with `.json` are parsed as JSON, all other files as TOML.
+    pub fn load(path: &Path) -> Result<Profile> {
+        let content = fs::read_to_string(path)?;
+
+        let profile = if is_json(path) {
+            serde_json::from_str(&content).map_err(|e| e.to_string())
+        }
+        else {
+            toml::from_str(&content).map_err(|e| e.to_string())
+        };
+
+        profile.map_err(|e| FootswitchError::InvalidProfile(format!("Could not parse {}: {}", path.display(), e)))
+    }
+
+    /// Save a profile, in the same format that `load` expects
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = if is_json(path) {
+            serde_json::to_string_pretty(self).map(|json| json + "\n").map_err(|e| e.to_string())
+        }
+        else {
+            toml::to_string(self).map_err(|e| e.to_string())
+        };
+
+        let content = content.map_err(|e| FootswitchError::InvalidProfile(format!("Could not serialize profile: {}", e)))?;
+
+        fs::write(path, content)?;
+
+        Ok(())
+    }
+}
+
+impl PedalProfile {
+    pub fn from_config(ped: u8, config: &PedalConfig) -> PedalProfile {
+        let mut profile = PedalProfile { pedal: ped, ..Default::default() };
+
+        match *config {
+            PedalConfig::Unconfigured => {},
+            PedalConfig::Key { modifiers, key } => {
+                profile.set_key(modifiers, key);
+            },
+            PedalConfig::Mouse { buttons, x, y, w } => {
+                profile.set_mouse(buttons, x, y, w);
+            },
+            PedalConfig::MouseKey { modifiers, key, buttons, x, y, w } => {
+                profile.set_key(modifiers, key);
+                profile.set_mouse(buttons, x, y, w);
+            },
+            PedalConfig::String { ref keys } => {
+                let names: Vec<String> = keys.iter().map(|key| key_name(*key)).collect();
+
+                // Use a plain string if every key is a single character
+                if names.iter().all(|name| name.chars().count() == 1) {
+                    profile.string = Some(names.concat());
+                }
+                else {
+                    profile.keys = names;
+                }
+            },
+        }
+
+        // Settings that are all empty would be read back as another type
+        if profile.to_config().ok().as_ref() != Some(config) {
+            profile.typ = Some(type_name(config).to_string());
+        }
+
+        profile
+    }
+
+    fn set_key(&mut self, modifiers: u8, key: u8) {
+        self.modifiers = flag_names(MODIFIERS, modifiers);
+
+        if key != 0 {
+            self.key = Some(key_name(key));
+        }
+    }
+
+    fn set_mouse(&mut self, buttons: u8, x: i8, y: i8, w: i8) {
+        self.mouse_buttons = flag_names(MOUSE_BUTTONS, buttons);
+        self.x = x;
+        self.y = y;
+        self.w = w;
+    }
+
+    pub fn to_config(&self) -> Result<PedalConfig> {
+        let mut config = PedalConfig::Unconfigured;
+
+        if let Some(ref key) = self.key {
+            config.set_key(key_code(key)?)?;
+        }
+
+        if !self.modifiers.is_empty() {
+            let modifiers = flags(MODIFIERS, &self.modifiers, "modifier")?;
+
+            match config {
+                PedalConfig::Unconfigured => config = PedalConfig::Key { modifiers, key: 0 },
+                PedalConfig::Key { modifiers: ref mut m, .. } => *m = modifiers,
+                _ => unreachable!(),
+            }
+        }
+
+        if !self.mouse_buttons.is_empty() || self.x != 0 || self.y != 0 || self.w != 0 {
+            let buttons = flags(MOUSE_BUTTONS, &self.mouse_buttons, "mouse button")?;
+
+            config = match config {
+                PedalConfig::Unconfigured => PedalConfig::Mouse { buttons, x: self.x, y: self.y, w: self.w },
+                PedalConfig::Key { modifiers, key } => {
+                    PedalConfig::MouseKey { modifiers, key, buttons, x: self.x, y: self.y, w: self.w }
+                },
+                _ => unreachable!(),
+            };
+        }
+
+        if self.string.is_some() || !self.keys.is_empty() {
+            let mut keys = match self.string {
+                Some(ref string) => match key_operations::encode_string(string) {
+                    Some(keys) => keys,
+                    None => return Err(FootswitchError::InvalidKey(
+                        format!("Could not encode string '{}'! It contains characters that are not listed in './footswitch-rs list -c 4'", string))),
+                },
+                None => Vec::new(),
+            };
+
+            for key in self.keys.iter() {
+                keys.push(key_code(key)?);
+            }
+
+            config.append_keys(&keys)?;
+        }
+
+        match self.typ {
+            Some(ref typ) => self.with_type(config, typ),
+            None => Ok(config),
+        }
+    }
+
+    /// Turns a configuration into the given type, e.g., a key without key and modifiers
+    fn with_type(&self, config: PedalConfig, typ: &str) -> Result<PedalConfig> {
+        Ok(match (typ, config) {
+            ("key", PedalConfig::Unconfigured) => PedalConfig::Key { modifiers: 0, key: 0 },
+            ("mouse", PedalConfig::Unconfigured) => PedalConfig::Mouse { buttons: 0, x: 0, y: 0, w: 0 },
+            ("mousekey", PedalConfig::Unconfigured) => PedalConfig::MouseKey { modifiers: 0, key: 0, buttons: 0, x: 0, y: 0, w: 0 },
+            ("mousekey", PedalConfig::Key { modifiers, key }) => PedalConfig::MouseKey { modifiers, key, buttons: 0, x: 0, y: 0, w: 0 },
+            ("mousekey", PedalConfig::Mouse { buttons, x, y, w }) => PedalConfig::MouseKey { modifiers: 0, key: 0, buttons, x, y, w },
+            ("string", PedalConfig::Unconfigured) => PedalConfig::String { keys: Vec::new() },
+            (typ, config) if type_name(&config) == typ => config,
+            ("key", _) | ("mouse", _) | ("mousekey", _) | ("string", _) => return Err(FootswitchError::InvalidProfile(
+                format!("Pedal {} has the type '{}', which does not match its settings.", self.pedal, typ))),
+            _ => return Err(FootswitchError::InvalidProfile(
+                format!("Pedal {} has the unknown type '{}'. Use key, mouse, mousekey, or string.", self.pedal, typ))),
+        })
+    }
+}
+
+/// Name of the type of a configuration, as it is given in profiles
+fn type_name(config: &PedalConfig) -> &'static str {
+    match *config {
+        PedalConfig::Unconfigured => "none",
+        PedalConfig::Key { .. } => "key",
+        PedalConfig::Mouse { .. } => "mouse",
+        PedalConfig::MouseKey { .. } => "mousekey",
+        PedalConfig::String { .. } => "string",
+    }
+}
+
+/// Differences between the configuration of a pedal and a wanted configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct PedalDiff {
+    pub pedal: u8,
+
+    /// One readable description per field that differs, e.g. `key b → c`
+    pub changes: Vec<String>,
+}
+
+impl fmt::Display for PedalDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pedal {}: {}", self.pedal, self.changes.join(", "))
+    }
+}
+
+/// Compare the current configuration of all pedals field by field with the
+/// wanted configuration. Pedals that do not differ are omitted.
+pub fn diff(current: &[PedalConfig], wanted: &[PedalConfig]) -> Vec<PedalDiff> {
+    current.iter().zip(wanted.iter()).enumerate().filter_map(|(ped, (current_config, wanted_config))| {
+        let current = PedalProfile::from_config(ped as u8, current_config);
+        let wanted = PedalProfile::from_config(ped as u8, wanted_config);
+
+        let mut changes = Vec::new();
+
+        if current.key != wanted.key {
+            changes.push(format!("key {} → {}", or_none(&current.key), or_none(&wanted.key)));
+        }
+
+        if let Some(change) = flag_changes(&current.modifiers, &wanted.modifiers) {
+            changes.push(format!("modifiers {}", change));
+        }
+
+        if let Some(change) = flag_changes(&current.mouse_buttons, &wanted.mouse_buttons) {
+            changes.push(format!("mouse buttons {}", change));
+        }
+
+        for &(name, current, wanted) in [("x", current.x, wanted.x), ("y", current.y, wanted.y), ("w", current.w, wanted.w)].iter() {
+            if current != wanted {
+                changes.push(format!("{} {} → {}", name, current, wanted));
+            }
+        }
+
+        let (current_string, wanted_string) = (current.string_text(), wanted.string_text());
+
+        if current_string != wanted_string {
+            changes.push(format!("string {} → {}", or_none(&current_string), or_none(&wanted_string)));
+        }
+
+        // Pedals whose settings are all empty only differ in their type
+        if changes.is_empty() && current_config.encode() != wanted_config.encode() {
+            changes.push(format!("type {} → {}", type_name(current_config), type_name(wanted_config)));
+        }
+
+        if changes.is_empty() {
+            None
+        }
+        else {
+            Some(PedalDiff { pedal: ped as u8, changes })
+        }
+    }).collect()
+}
+
+impl PedalProfile {
+    /// The string of the pedal, with all keys that are not a single character in angle brackets
+    fn string_text(&self) -> Option<String> {
+        if self.string.is_none() && self.keys.is_empty() {
+            return None;
+        }
+
+        let mut text = format!("\"{}", self.string.clone().unwrap_or_default());
+
+        for key in self.keys.iter() {
+            if key.chars().count() == 1 {
+                text.push_str(key);
+            }
+            else {
+                text.push_str(&format!("<{}>", key));
+            }
+        }
+
+        text.push('"');
+
+        Some(text)
+    }
+}
+
+fn or_none(value: &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "none".to_string())
+}
+
+/// Flags that were added (`+ctrl`) and removed (`-alt`), or `None` if nothing changed
+fn flag_changes(current: &[String], wanted: &[String]) -> Option<String> {
+    let mut changes: Vec<String> = wanted.iter().filter(|flag| !current.contains(flag)).map(|flag| format!("+{}", flag)).collect();
+    changes.extend(current.iter().filter(|flag| !wanted.contains(flag)).map(|flag| format!("-{}", flag)));
+
+    if changes.is_empty() {
+        None
+    }
+    else {
+        Some(changes.join(" "))
+    }
+}
+
+/// Name of a key that encodes to the same key code again, or its code in hexadecimal
+fn key_name(code: u8) -> String {
+    match key_operations::decode_byte(&code) {
+        // decode_byte returns the name in angle brackets
+        Some(ref name) if key_operations::encode_byte(&name[1..(name.len() - 1)]) == Some(code) => {
+            name[1..(name.len() - 1)].to_string()
+        },
+        _ => format!("{:#04x}", code),
+    }
+}
+
+/// Code of a key, given by its name or by its code in hexadecimal
+fn key_code(name: &str) -> Result<u8> {
+    if let Some(code) = key_operations::encode_byte(name) {
+        return Ok(code);
+    }
+
+    if let Some(hex) = name.strip_prefix("0x") {
+        if let Ok(code) = u8::from_str_radix(hex, 16) {
+            return Ok(code);
+        }
+    }
+
+    Err(FootswitchError::InvalidKey(
+        format!("Key '{}' is not recognized! Please provide a valid key, listed in './footswitch-rs list -c 4'", name)))
+}
+
+fn flag_names(table: &[(&str, u8)], value: u8) -> Vec<String> {
+    table.iter().filter(|&&(_, flag)| value & flag != 0).map(|&(name, _)| name.to_string()).collect()
+}
+
+fn flags(table: &[(&str, u8)], names: &[String], kind: &str) -> Result<u8> {
+    let mut value = 0;
+
+    for name in names.iter() {
+        match table.iter().find(|&&(other, _)| other == name) {
+            Some(&(_, flag)) => value |= flag,
+            None => return Err(FootswitchError::InvalidKey(format!("Unknown {} '{}'! Please use one of the following: {}.",
+                                                                   kind, name, flag_names(table, 0xff).join(", ")))),
+        }
+    }
+
+    Ok(value)
+}
+
+/// Directory in which named profiles are stored
+pub fn profiles_dir() -> PathBuf {
+    config::config_dir().join("profiles")
+}
+
+/// File of a named profile
+pub fn profile_file(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') || name.contains('\\') {
+        return Err(FootswitchError::InvalidProfile(
+            format!("Invalid profile name '{}'. Names may not be empty, start with a dot, or contain slashes.", name)));
+    }
+
+    Ok(profiles_dir().join(format!("{}.toml", name)))
+}
+
+/// Load a named profile
+pub fn load_named(name: &str) -> Result<Profile> {
+    let path = profile_file(name)?;
+
+    if !path.exists() {
+        return Err(FootswitchError::InvalidProfile(format!("There is no profile named '{}'.", name)));
+    }
+
+    Profile::load(&path)
+}
+
+/// Load a profile from a file or, if there is no such file, a named profile
+pub fn load_file_or_named(name: &str) -> Result<Profile> {
+    let path = Path::new(name);
+
+    if path.is_file() {
+        Profile::load(path)
+    }
+    else {
+        load_named(name)
+    }
+}
+
+/// Save a named profile. An existing profile with the same name is overwritten.
+pub fn save_named(name: &str, profile: &Profile) -> Result<PathBuf> {
+    let path = profile_file(name)?;
+
+    fs::create_dir_all(profiles_dir())?;
+    profile.save(&path)?;
+
+    Ok(path)
+}
+
+/// Delete a named profile
+pub fn delete_named(name: &str) -> Result<()> {
+    match fs::remove_file(profile_file(name)?) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(FootswitchError::InvalidProfile(format!("There is no profile named '{}'.", name)))
+        },
+        result => Ok(result?),
+    }
+}
+
+/// Names of all stored profiles, sorted alphabetically
+pub fn list_named() -> Result<Vec<String>> {
+    let entries = match fs::read_dir(profiles_dir()) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut names = Vec::new();
+
+    for entry in entries {
+        let path = entry?.path();
+
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            if let Some(name) = path.file_stem() {
+                names.push(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    names.sort();
+
+    Ok(names)
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn diff_of_identical_configs_is_empty() {
+        let configs = vec![
+            PedalConfig::Key { modifiers: 1, key: 0x05 },
+            PedalConfig::Mouse { buttons: 1, x: -3, y: 0, w: 1 },
+            PedalConfig::String { keys: vec![0x0b, 0x0c, 0x28] },
+        ];
+
+        assert!(diff(&configs, &configs).is_empty());
+    }
+
+    #[test]
+    fn diff_describes_every_field() {
+        let current = vec![
+            PedalConfig::Unconfigured,
+            PedalConfig::Key { modifiers: 4, key: 0x05 },
+            PedalConfig::Mouse { buttons: 1, x: 0, y: 0, w: 0 },
+        ];
+
+        let wanted = vec![
+            PedalConfig::Unconfigured,
+            PedalConfig::Key { modifiers: 1, key: 0x06 },
+            PedalConfig::String { keys: vec![0x0b, 0x0c, 0x28] },
+        ];
+
+        let diffs: Vec<String> = diff(&current, &wanted).iter().map(|diff| diff.to_string()).collect();
+
+        assert_eq!(diffs, vec![
+            "pedal 1: key b → c, modifiers +ctrl -alt".to_string(),
+            "pedal 2: mouse buttons -left, string none → \"hi<enter>\"".to_string(),
+        ]);
+    }
+
+    fn all_types() -> Vec<PedalConfig> {
+        vec![
+            PedalConfig::MouseKey { modifiers: 0x03, key: 0x06, buttons: 0x01, x: -5, y: 5, w: 1 },
+            PedalConfig::String { keys: vec![0x0b, 0x0c, 0x28] },
+            PedalConfig::Unconfigured,
+        ]
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("footswitch-rs-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn configs_round_trip() {
+        let configs = vec![
+            PedalConfig::Key { modifiers: 0x09, key: 0x04 },
+            PedalConfig::Key { modifiers: 0x02, key: 0x00 },
+            PedalConfig::Mouse { buttons: 0x02, x: 0, y: -128, w: 127 },
+        ];
+
+        for configs in [configs, all_types()].iter() {
+            assert_eq!(Profile::from_configs(configs).to_configs().unwrap(), *configs);
+        }
+
+        // Strings with keys that are not single characters are listed as keys
+        let profile = Profile::from_configs(&all_types());
+        assert_eq!(profile.pedal[1].string, None);
+        assert_eq!(profile.pedal[1].keys, vec!["h", "i", "enter"]);
+    }
+
+    #[test]
+    fn empty_settings_round_trip_through_bytes() {
+        let configs = [
+            PedalConfig::Key { modifiers: 0, key: 0 },
+            PedalConfig::Mouse { buttons: 0, x: 0, y: 0, w: 0 },
+            PedalConfig::MouseKey { modifiers: 0, key: 0, buttons: 0x01, x: 0, y: 0, w: 0 },
+            PedalConfig::MouseKey { modifiers: 0x01, key: 0x04, buttons: 0, x: 0, y: 0, w: 0 },
+            PedalConfig::MouseKey { modifiers: 0, key: 0, buttons: 0, x: 0, y: 0, w: 0 },
+            PedalConfig::String { keys: Vec::new() },
+        ];
+
+        for chunk in configs.chunks(3) {
+            let bytes: Vec<Vec<u8>> = chunk.iter().map(|config| config.encode()).collect();
+            let decoded: Vec<PedalConfig> = bytes.iter().map(|data| PedalConfig::decode(data).unwrap()).collect();
+
+            let toml = toml::to_string(&Profile::from_configs(&decoded)).unwrap();
+            let imported = toml::from_str::<Profile>(&toml).unwrap().to_configs().unwrap();
+
+            assert_eq!(imported.iter().map(|config| config.encode()).collect::<Vec<_>>(), bytes, "{}", toml);
+            assert!(diff(&decoded, &imported).is_empty());
+        }
+
+        assert_eq!(Profile::from_configs(&configs[..1]).pedal[0].typ, Some("key".to_string()));
+        assert_eq!(Profile::from_configs(&all_types()).pedal.iter().filter(|pedal| pedal.typ.is_some()).count(), 0);
+
+        let changes = diff(&[PedalConfig::Unconfigured], &configs[..1]);
+        assert_eq!(changes[0].changes, vec!["type none → key"]);
+    }
+
+    #[test]
+    fn rejects_mismatching_types() {
+        let wrong = PedalProfile { pedal: 1, typ: Some("mouse".to_string()), key: Some("a".to_string()), ..Default::default() };
+        assert!(matches!(wrong.to_config(), Err(FootswitchError::InvalidProfile(_))));
+
+        let unknown = PedalProfile { pedal: 1, typ: Some("keyboard".to_string()), ..Default::default() };
+        assert!(matches!(unknown.to_config(), Err(FootswitchError::InvalidProfile(_))));
+    }
+
+    #[test]
+    fn save_and_load_toml_and_json() {
+        let profile = Profile::from_configs(&all_types());
+
+        for name in ["profile.toml", "profile.json"].iter() {
+            let path = temp_file(name);
+
+            profile.save(&path).unwrap();
+            let content = fs::read_to_string(&path).unwrap();
+            let loaded = Profile::load(&path);
+            fs::remove_file(&path).unwrap();
+
+            assert_eq!(loaded.unwrap(), profile);
+            assert_eq!(content.trim_start().starts_with('{'), name.ends_with(".json"));
+        }
+    }
+
+    #[test]
+    fn parses_the_documented_format() {
+        let profile: Profile = toml::from_str(r#"
+            [[pedal]]
+            pedal = 0
+            key = "c"
+            modifiers = ["ctrl"]
+
+            [[pedal]]
+            pedal = 2
+            string = "hello"
+            keys = ["enter", "0x32"]
+        "#).unwrap();
+
+        assert_eq!(profile.to_configs().unwrap(), vec![
+            PedalConfig::Key { modifiers: 0x01, key: 0x06 },
+            PedalConfig::Unconfigured,
+            PedalConfig::String { keys: vec![0x0b, 0x08, 0x0f, 0x0f, 0x12, 0x28, 0x32] },
+        ]);
+
+        assert!(toml::from_str::<Profile>("[[pedal]]\npedal = 0\nkye = \"c\"\n").is_err());
+    }
+
+    #[test]
+    fn rejects_contradicting_settings() {
+        let invalid = vec![
+            PedalProfile { pedal: 0, key: Some("a".to_string()), string: Some("b".to_string()), ..Default::default() },
+            PedalProfile { pedal: 0, mouse_buttons: vec!["left".to_string()], keys: vec!["enter".to_string()], ..Default::default() },
+        ];
+
+        for pedal in invalid.into_iter() {
+            match (Profile { pedal: vec![pedal] }).to_configs() {
+                Err(FootswitchError::InvalidProfile(_)) => {},
+                result => panic!("Expected an invalid profile, got {:?}", result),
+            }
+        }
+
+        let twice = Profile { pedal: vec![PedalProfile { pedal: 1, ..Default::default() }; 2] };
+        assert!(matches!(twice.to_configs(), Err(FootswitchError::InvalidProfile(_))));
+
+        let unknown_pedal = Profile { pedal: vec![PedalProfile { pedal: 3, ..Default::default() }] };
+        assert!(matches!(unknown_pedal.to_configs(), Err(FootswitchError::InvalidPedal(3))));
+
+        let unknown_modifier = PedalProfile { pedal: 0, modifiers: vec!["hyper".to_string()], ..Default::default() };
+        assert!(matches!(unknown_modifier.to_config(), Err(FootswitchError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn validates_profile_names() {
+        for name in ["", ".", "..", "../work", "a/b", "a\\b", ".hidden"].iter() {
+            assert!(profile_file(name).is_err(), "'{}' should be rejected", name);
+        }
+
+        assert_eq!(profile_file("vim clutch").unwrap(), profiles_dir().join("vim clutch.toml"));
+    }
+}
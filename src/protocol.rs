@@ -0,0 +1,194 @@
+//! Byte protocol of the PCSensor configuration interface
+//!
+//! All communication happens in packets of (at most) 8 bytes. A write session
+//! looks as follows:
+//!
+//!  * `01 80 08 00 00 00 00 00` — start packet, announces that pedals will be written
+//!  * `01 81 LL PP 00 00 00 00` — header for pedal `PP - 1`, followed by `LL` data bytes
+//!  * `LL TT .. .. .. .. .. ..` — the data bytes, sent in chunks of 8 bytes. The last
+//!    chunk is shorter if `LL` is not a multiple of 8.
+//!
+//! Reading a pedal is done by writing a query:
+//!
+//!  * `01 82 08 PP 00 00 00 00` — query for pedal `PP - 1`
+//!
+//! The device answers with the data bytes of that pedal, again in chunks of 8 bytes.
+//! The first byte of the data is always the length `LL` of the data and the second
+//! byte `TT` the type of the pedal (see `Type`). What follows depends on the type:
+//!
+//!  * Key/Mouse: `LL TT MM KK BB XX YY WW` with modifiers `MM`, key `KK`, mouse
+//!    buttons `BB`, and the mouse movement `XX`, `YY`, `WW`.
+//!  * String: `LL TT C0 C1 ...` with `LL - 2` encoded keys.
+
+use pedal_config::Type;
+
+/// Size of a single packet
+pub const PACKET_SIZE: usize = 8;
+
+/// Number of pedals of a foot switch
+pub const NUM_PEDALS: usize = 3;
+
+/// Maximum number of data bytes a pedal can store
+pub const MAX_DATA_LEN: usize = 48;
+
+/// First byte of every packet that is not a data packet
+pub const REPORT_ID: u8 = 0x01;
+
+/// Second byte of the start packet
+pub const START: u8 = 0x80;
+
+/// Second byte of a header packet
+pub const HEADER: u8 = 0x81;
+
+/// Second byte of a query packet
+pub const QUERY: u8 = 0x82;
+
+pub fn start_packet() -> [u8; 8] {
+    [REPORT_ID, START, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00]
+}
+
+pub fn header_packet(ped: u8, len: u8) -> [u8; 8] {
+    [REPORT_ID, HEADER, len, ped + 1, 0x00, 0x00, 0x00, 0x00]
+}
+
+pub fn query_packet(ped: u8) -> [u8; 8] {
+    [REPORT_ID, QUERY, 0x08, ped + 1, 0x00, 0x00, 0x00, 0x00]
+}
+
+/// Data of a pedal that has not been configured
+pub fn unconfigured_data() -> [u8; 8] {
+    [0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+}
+
+/// Format bytes as space separated hexadecimal values
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(" ")
+}
+
+/// Parse space separated hexadecimal values
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.split_whitespace().map(|b| u8::from_str_radix(b, 16).ok()).collect()
+}
+
+/// Describes the packets that are exchanged with a device in words. Data
+/// packets can only be understood in the context of the header or query before
+/// them, so the packets of a session must be passed in order.
+#[derive(Default, Debug)]
+pub struct Describer {
+    /// Pedal, data length, and number of data bytes that were already written
+    data: Option<(u8, usize, usize)>,
+
+    /// Pedal that was queried, its data length (once known), and number of bytes that were already read
+    response: Option<(u8, Option<usize>, usize)>,
+}
+
+impl Describer {
+    pub fn new() -> Describer {
+        Describer::default()
+    }
+
+    /// Describe a packet that is written to the device
+    pub fn outgoing(&mut self, packet: &[u8]) -> String {
+        if let Some((ped, len, offset)) = self.data {
+            let end = (offset + packet.len()).min(len);
+
+            self.data = if end < len { Some((ped, len, end)) } else { None };
+
+            let mut description = format!("data of pedal {}, bytes {}..{}", ped, offset, end);
+
+            if offset == 0 && packet.len() >= 2 {
+                description.push_str(&format!(": length {}, type {}", packet[0], describe_type(packet[1])));
+            }
+
+            return description;
+        }
+
+        if packet.len() < 4 || packet[0] != REPORT_ID {
+            return "unknown packet".to_string();
+        }
+
+        match packet[1] {
+            START => "start of a write session".to_string(),
+            HEADER if packet[3] > 0 => {
+                self.data = Some((packet[3] - 1, packet[2] as usize, 0));
+
+                format!("header of pedal {}, followed by {} data bytes", packet[3] - 1, packet[2])
+            },
+            QUERY if packet[3] > 0 => {
+                self.response = Some((packet[3] - 1, None, 0));
+
+                format!("query of pedal {}", packet[3] - 1)
+            },
+            _ => "unknown packet".to_string(),
+        }
+    }
+
+    /// Describe a packet that is read from the device
+    pub fn incoming(&mut self, packet: &[u8]) -> String {
+        let (ped, len, offset) = match self.response {
+            Some(response) => response,
+            None => return "unexpected packet, no query is pending".to_string(),
+        };
+
+        let end = offset + packet.len();
+        let mut description = format!("response of pedal {}, bytes {}..{}", ped, offset, end);
+
+        let len = match len {
+            Some(len) => len,
+            None if packet.len() >= 2 => {
+                description.push_str(&format!(": length {}, type {}", packet[0], describe_type(packet[1])));
+                packet[0] as usize
+            },
+            None => 0,
+        };
+
+        self.response = if end < len { Some((ped, Some(len), end)) } else { None };
+
+        description
+    }
+}
+
+fn describe_type(typ: u8) -> String {
+    match Type::u8_to_enum(typ) {
+        Some(t) => format!("{:?} ({:#04x})", t, typ),
+        None => format!("unknown ({:#04x})", typ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_writes_of_strings() {
+        let mut describer = Describer::new();
+
+        // "hello world" spans two data packets
+        let string = [0x0d, 0x04, 0x0b, 0x08, 0x0f, 0x0f, 0x12, 0x2c, 0x1a, 0x12, 0x15, 0x0f, 0x07];
+
+        assert_eq!(describer.outgoing(&start_packet()), "start of a write session");
+        assert_eq!(describer.outgoing(&header_packet(1, 13)), "header of pedal 1, followed by 13 data bytes");
+        assert_eq!(describer.outgoing(&string[..8]), "data of pedal 1, bytes 0..8: length 13, type String (0x04)");
+        assert_eq!(describer.outgoing(&string[8..]), "data of pedal 1, bytes 8..13");
+
+        // The data is complete, so the next packet is a command again
+        assert_eq!(describer.outgoing(&header_packet(2, 8)), "header of pedal 2, followed by 8 data bytes");
+        assert_eq!(describer.outgoing(&[0x08, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00]), "data of pedal 2, bytes 0..8: length 8, type Key (0x01)");
+        assert_eq!(describer.outgoing(&[0x02, 0x80, 0x00]), "unknown packet");
+    }
+
+    #[test]
+    fn describes_responses() {
+        let mut describer = Describer::new();
+
+        assert_eq!(describer.incoming(&[0x08, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00]), "unexpected packet, no query is pending");
+
+        assert_eq!(describer.outgoing(&query_packet(0)), "query of pedal 0");
+        assert_eq!(describer.incoming(&[0x0d, 0x04, 0x0b, 0x08, 0x0f, 0x0f, 0x12, 0x2c]), "response of pedal 0, bytes 0..8: length 13, type String (0x04)");
+        assert_eq!(describer.incoming(&[0x1a, 0x12, 0x15, 0x0f, 0x07, 0x00, 0x00, 0x00]), "response of pedal 0, bytes 8..16");
+        assert_eq!(describer.incoming(&[0x08, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00]), "unexpected packet, no query is pending");
+
+        assert_eq!(describer.outgoing(&query_packet(2)), "query of pedal 2");
+        assert_eq!(describer.incoming(&[0x08, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), "response of pedal 2, bytes 0..8: length 8, type unknown (0x07)");
+    }
+}
@@ -0,0 +1,175 @@
+//! Recording and replaying of the communication with a device
+//!
+//! `Recorder` wraps another transport and writes every packet to a session
+//! file. `Replay` is a transport that plays such a file back: it checks that
+//! every write matches the recorded one and answers reads with the recorded
+//! responses. That way, sessions that were captured on a real device can be
+//! used to test footswitch-rs without the device.
+//!
+//! Session files are text files with one packet per line:
+//!
+//! ```text
+//! # footswitch-rs session
+//! # Recorded by footswitch-rs 0.1.0: footswitch-rs read -p 0
+//! # Device: 0c45:7403 at 1-2:1.1 (serial: unknown)
+//! version 1
+//! > 01 82 08 01 00 00 00 00
+//! < 08 01 00 04 00 00 00 00
+//! ! Timed out
+//! ```
+//!
+//! `>` are writes, `<` reads, and `!` reads that failed. Lines starting with
+//! `#` are comments; the ones at the top name the command and the device.
+
+use std::io;
+use std::io::Write;
+use std::fs;
+use std::path::Path;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use transport::Transport;
+use protocol;
+
+/// Version of the session format that is written
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Write(Vec<u8>),
+    Read(Vec<u8>),
+    ReadError(String),
+}
+
+impl Event {
+    fn to_line(&self) -> String {
+        match *self {
+            Event::Write(ref data) => format!("> {}", protocol::to_hex(data)),
+            Event::Read(ref data) => format!("< {}", protocol::to_hex(data)),
+            Event::ReadError(ref msg) => format!("! {}", msg),
+        }
+    }
+}
+
+/// Parse a session file
+pub fn parse(content: &str) -> Result<Vec<Event>, String> {
+    let mut lines = content.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+    match lines.next() {
+        Some(line) if line == format!("version {}", VERSION) => {},
+        Some(line) if line.starts_with("version ") => return Err(format!("Unsupported session {}.", line)),
+        _ => return Err("The session does not start with a version.".to_string()),
+    }
+
+    lines.map(|line| {
+        let (kind, rest) = line.split_at(1);
+        let hex = || protocol::from_hex(rest).ok_or_else(|| format!("Invalid line '{}'.", line));
+
+        match kind {
+            ">" => Ok(Event::Write(hex()?)),
+            "<" => Ok(Event::Read(hex()?)),
+            "!" => Ok(Event::ReadError(rest.trim().to_string())),
+            _ => Err(format!("Invalid line '{}'.", line)),
+        }
+    }).collect()
+}
+
+/// Transport that records all packets of another transport
+pub struct Recorder<T: Transport> {
+    inner: T,
+    log: RefCell<Box<dyn Write>>,
+}
+
+impl<T: Transport> Recorder<T> {
+    /// Start a session. `description` is written as a comment, e.g., to name the device.
+    pub fn new(inner: T, mut log: Box<dyn Write>, description: &str) -> io::Result<Recorder<T>> {
+        writeln!(log, "# footswitch-rs session")?;
+
+        for line in description.lines() {
+            writeln!(log, "# {}", line)?;
+        }
+
+        writeln!(log, "version {}", VERSION)?;
+
+        Ok(Recorder { inner, log: RefCell::new(log) })
+    }
+
+    fn record(&self, event: Event) -> io::Result<()> {
+        let mut log = self.log.borrow_mut();
+
+        writeln!(log, "{}", event.to_line())?;
+        log.flush()
+    }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+    fn write(&self, data: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(data)?;
+        self.record(Event::Write(data.to_vec()))?;
+
+        Ok(len)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(len) => {
+                self.record(Event::Read(buf[..len].to_vec()))?;
+                Ok(len)
+            },
+            Err(e) => {
+                self.record(Event::ReadError(e.to_string()))?;
+                Err(e)
+            },
+        }
+    }
+}
+
+/// Transport that plays a recorded session back
+pub struct Replay {
+    events: RefCell<VecDeque<Event>>,
+}
+
+impl Replay {
+    pub fn new(events: Vec<Event>) -> Replay {
+        Replay { events: RefCell::new(events.into_iter().collect()) }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Replay> {
+        let content = fs::read_to_string(path)?;
+        let events = parse(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(Replay::new(events))
+    }
+
+    /// Number of recorded packets that were not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.events.borrow().len()
+    }
+}
+
+impl Transport for Replay {
+    fn write(&self, data: &[u8]) -> io::Result<usize> {
+        match self.events.borrow_mut().pop_front() {
+            Some(Event::Write(ref expected)) if expected.as_slice() == data => Ok(data.len()),
+            Some(event) => Err(replay_error(format!("Expected '{}', but '{}' was written.", event.to_line(), protocol::to_hex(data)))),
+            None => Err(replay_error(format!("The session ended, but '{}' was written.", protocol::to_hex(data)))),
+        }
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.events.borrow_mut().pop_front() {
+            Some(Event::Read(ref data)) => {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+
+                Ok(len)
+            },
+            Some(Event::ReadError(msg)) => Err(io::Error::other(msg)),
+            Some(event) => Err(replay_error(format!("Expected '{}', but a packet was read.", event.to_line()))),
+            None => Err(replay_error("The session ended, but a packet was read.".to_string())),
+        }
+    }
+}
+
+fn replay_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Replay diverged from the recorded session. {}", msg))
+}
@@ -0,0 +1,121 @@
+//! Logging of all packets that are exchanged with a device
+//!
+//! `Trace` wraps another transport and writes one line per packet to a log:
+//!
+//! ```text
+//! 1760790000.123456 OUT 01 82 08 01 00 00 00 00   query of pedal 0
+//! 1760790000.125012 IN  08 01 00 04 00 00 00 00   response of pedal 0, bytes 0..8: length 8, type Key (0x01)
+//! ```
+//!
+//! The timestamp is the number of seconds since the UNIX epoch.
+
+use std::io;
+use std::io::Write;
+use std::cell::RefCell;
+use std::time::{SystemTime, UNIX_EPOCH};
+use transport::Transport;
+use protocol;
+use protocol::Describer;
+
+pub struct Trace<T: Transport> {
+    inner: T,
+    log: RefCell<Box<dyn Write>>,
+    describer: RefCell<Describer>,
+}
+
+impl<T: Transport> Trace<T> {
+    pub fn new(inner: T, log: Box<dyn Write>) -> Trace<T> {
+        Trace {
+            inner,
+            log: RefCell::new(log),
+            describer: RefCell::new(Describer::new()),
+        }
+    }
+
+    fn log(&self, direction: &str, entry: &str) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        // A trace must never make the communication with the device fail
+        let mut log = self.log.borrow_mut();
+        let _ = writeln!(log, "{}.{:06} {:<3} {}", now.as_secs(), now.subsec_micros(), direction, entry);
+        let _ = log.flush();
+    }
+}
+
+impl<T: Transport> Transport for Trace<T> {
+    fn write(&self, data: &[u8]) -> io::Result<usize> {
+        let description = self.describer.borrow_mut().outgoing(data);
+
+        match self.inner.write(data) {
+            Ok(len) => {
+                self.log("OUT", &format!("{:<23}   {}", protocol::to_hex(data), description));
+                Ok(len)
+            },
+            Err(e) => {
+                self.log("OUT", &format!("{:<23}   {}, failed: {}", protocol::to_hex(data), description, e));
+                Err(e)
+            },
+        }
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(len) => {
+                let description = self.describer.borrow_mut().incoming(&buf[..len]);
+                self.log("IN", &format!("{:<23}   {}", protocol::to_hex(&buf[..len]), description));
+                Ok(len)
+            },
+            Err(e) => {
+                self.log("IN", &format!("read failed: {}", e));
+                Err(e)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use transport::MockTransport;
+
+    /// Log whose content stays accessible after it was passed to `Trace`
+    #[derive(Clone, Default)]
+    struct SharedLog(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedLog {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn logs_packets_with_descriptions() {
+        let log = SharedLog::default();
+        let mock = MockTransport::new();
+        mock.push_reply(&[0x08, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00]);
+        mock.push_reply(&[0x08, 0x01, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00]);
+
+        let trace = Trace::new(mock, Box::new(log.clone()));
+        let mut buf = [0u8; 8];
+
+        trace.write(&protocol::query_packet(0)).unwrap();
+        trace.read(&mut buf).unwrap();
+        trace.read(&mut buf).unwrap();
+        assert!(trace.read(&mut buf).is_err());
+
+        let content = String::from_utf8(log.0.borrow().clone()).unwrap();
+        let entries: Vec<&str> = content.lines().map(|line| line.split_once(' ').unwrap().1).collect();
+
+        assert_eq!(entries, vec![
+            "OUT 01 82 08 01 00 00 00 00   query of pedal 0",
+            "IN  08 01 00 04 00 00 00 00   response of pedal 0, bytes 0..8: length 8, type Key (0x01)",
+            "IN  08 01 00 05 00 00 00 00   unexpected packet, no query is pending",
+            "IN  read failed: No scripted reply left in mock transport",
+        ]);
+    }
+}
@@ -0,0 +1,152 @@
+//! Transports carry the 8 byte packets between `Pedals` and a foot switch.
+//!
+//! The real device is reached through hidapi, but everything that talks to the
+//! pedals only relies on the `Transport` trait. That way, other backends (e.g.,
+//! the `MockTransport` below) can be plugged in without touching the logic.
+
+use std::io;
+use std::io::{Read, Write};
+use std::fs::{File, OpenOptions};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use hidapi;
+
+pub trait Transport {
+    /// Write one packet to the device and return the number of bytes written
+    fn write(&self, data: &[u8]) -> io::Result<usize>;
+
+    /// Read one packet from the device into `buf` and return its length
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+impl Transport for hidapi::HidDevice {
+    fn write(&self, data: &[u8]) -> io::Result<usize> {
+        hidapi::HidDevice::write(self, data).map_err(hid_to_io)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        hidapi::HidDevice::read(self, buf).map_err(hid_to_io)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &T {
+    fn write(&self, data: &[u8]) -> io::Result<usize> {
+        (**self).write(data)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read(buf)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn write(&self, data: &[u8]) -> io::Result<usize> {
+        (**self).write(data)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read(buf)
+    }
+}
+
+fn hid_to_io(err: hidapi::HidError) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+/// Device that is accessed through its hidraw node, without hidapi. This is
+/// used for virtual devices (see `uhid`), which hidapi does not list.
+pub struct Hidraw {
+    file: File,
+}
+
+impl Hidraw {
+    pub fn open(path: &str) -> io::Result<Hidraw> {
+        Ok(Hidraw { file: OpenOptions::new().read(true).write(true).open(path)? })
+    }
+}
+
+impl Transport for Hidraw {
+    fn write(&self, data: &[u8]) -> io::Result<usize> {
+        (&self.file).write(data)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        (&self.file).read(buf)
+    }
+}
+
+/// A foot switch that was opened by `pedal_operations::open_device`
+pub enum Device {
+    Hid(hidapi::HidDevice),
+    Hidraw(Hidraw),
+}
+
+impl Transport for Device {
+    fn write(&self, data: &[u8]) -> io::Result<usize> {
+        match *self {
+            Device::Hid(ref dev) => dev.write(data).map_err(hid_to_io),
+            Device::Hidraw(ref dev) => dev.write(data),
+        }
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Device::Hid(ref dev) => dev.read(buf).map_err(hid_to_io),
+            Device::Hidraw(ref dev) => dev.read(buf),
+        }
+    }
+}
+
+/// In-memory transport that records every written packet and answers reads
+/// with replies that were scripted beforehand.
+#[derive(Default)]
+pub struct MockTransport {
+    written: RefCell<Vec<Vec<u8>>>,
+    replies: RefCell<VecDeque<Vec<u8>>>,
+}
+
+impl MockTransport {
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    /// Queue a packet that will be returned by the next unanswered `read`
+    pub fn push_reply(&self, reply: &[u8]) {
+        self.replies.borrow_mut().push_back(reply.to_vec());
+    }
+
+    /// All packets that were written so far, in order
+    pub fn written(&self) -> Vec<Vec<u8>> {
+        self.written.borrow().clone()
+    }
+
+    /// Forget all packets that were written so far
+    pub fn clear_written(&self) {
+        self.written.borrow_mut().clear();
+    }
+
+    /// Number of scripted replies that have not been read yet
+    pub fn pending_replies(&self) -> usize {
+        self.replies.borrow().len()
+    }
+}
+
+impl Transport for MockTransport {
+    fn write(&self, data: &[u8]) -> io::Result<usize> {
+        self.written.borrow_mut().push(data.to_vec());
+
+        Ok(data.len())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.replies.borrow_mut().pop_front() {
+            Some(reply) => {
+                let len = reply.len().min(buf.len());
+                buf[..len].copy_from_slice(&reply[..len]);
+
+                Ok(len)
+            },
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "No scripted reply left in mock transport")),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! udev rules that allow users to access foot switches without root
+//!
+//! The rule matches every supported device, both through its hidraw node and
+//! through its USB device node (used by the libusb backend of hidapi), and
+//! its event devices, which `daemon` grabs.
+
+use devices::DeviceTable;
+
+/// Where the rule should be installed. The number must be smaller than 73,
+/// so that systemd-logind applies the `uaccess` tag.
+pub const RULE_FILE: &str = "/etc/udev/rules.d/70-footswitch-rs.rules";
+
+/// Who gets access to the devices
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    /// All members of a group
+    Group(String),
+
+    /// The user who is logged in locally, through the `uaccess` tag of systemd
+    Uaccess,
+}
+
+/// Generate a rule for all devices of the table
+pub fn rule(table: &DeviceTable, access: &Access) -> String {
+    let permissions = match *access {
+        Access::Group(ref group) => format!("MODE=\"0660\", GROUP=\"{}\"", group),
+        Access::Uaccess => "TAG+=\"uaccess\"".to_string(),
+    };
+
+    let mut rule = format!("# Access to foot switches without root, generated by footswitch-rs {}\n", env!("CARGO_PKG_VERSION"));
+    rule.push_str(&format!("# Install it as {} and reconnect the foot switch.\n", RULE_FILE));
+
+    for id in table.ids().iter() {
+        rule.push_str(&format!("\n# {:04x}:{:04x}\n", id.vendor_id, id.product_id));
+        rule.push_str(&format!("SUBSYSTEM==\"hidraw\", ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\", {}\n",
+                               id.vendor_id, id.product_id, permissions));
+        rule.push_str(&format!("SUBSYSTEM==\"usb\", ATTR{{idVendor}}==\"{:04x}\", ATTR{{idProduct}}==\"{:04x}\", {}\n",
+                               id.vendor_id, id.product_id, permissions));
+        rule.push_str(&format!("SUBSYSTEM==\"input\", KERNEL==\"event*\", ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\", {}\n",
+                               id.vendor_id, id.product_id, permissions));
+    }
+
+    rule
+}
+
+/// Explains how to install the rule
+pub fn install_instructions() -> String {
+    format!("To use the foot switch without root, install a udev rule with 'footswitch-rs udev-rule | sudo tee {}', \
+             reload the rules with 'sudo udevadm control --reload-rules && sudo udevadm trigger', and reconnect the foot switch.",
+            RULE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rule_covers_all_devices() {
+        let rule = rule(&DeviceTable::default(), &Access::Group("plugdev".to_string()));
+
+        assert!(rule.contains("SUBSYSTEM==\"hidraw\", ATTRS{idVendor}==\"0c45\", ATTRS{idProduct}==\"7403\", MODE=\"0660\", GROUP=\"plugdev\"\n"));
+        assert!(rule.contains("SUBSYSTEM==\"usb\", ATTR{idVendor}==\"413d\", ATTR{idProduct}==\"2107\", MODE=\"0660\", GROUP=\"plugdev\"\n"));
+        assert!(rule.contains("SUBSYSTEM==\"input\", KERNEL==\"event*\", ATTRS{idVendor}==\"0c45\", ATTRS{idProduct}==\"7404\", MODE=\"0660\", GROUP=\"plugdev\"\n"));
+        assert_eq!(rule.matches("SUBSYSTEM==").count(), 9);
+    }
+
+    #[test]
+    fn rule_with_uaccess() {
+        let rule = rule(&DeviceTable::default(), &Access::Uaccess);
+
+        assert!(rule.contains("SUBSYSTEM==\"hidraw\", ATTRS{idVendor}==\"0c45\", ATTRS{idProduct}==\"7404\", TAG+=\"uaccess\"\n"));
+        assert!(!rule.contains("GROUP"));
+    }
+}
@@ -0,0 +1,255 @@
+//! Virtual foot switch through the Linux uhid driver
+//!
+//! `VirtualPedal` registers two HID devices at `/dev/uhid` that mimic the two
+//! interfaces of a PCSensor foot switch (0c45:7403):
+//!
+//!  * interface 0, a boot keyboard, which is what the pedals use to type
+//!  * interface 1, the configuration channel, whose packets are answered by an `Emulator`
+//!
+//! The kernel exposes both as regular hidraw nodes. uhid devices have no USB
+//! parent, so hidapi does not list them and can not open them.
+//! `devices::virtual_devices` finds them instead, with the interface number
+//! that is encoded in their physical path, and `open_device` opens them
+//! through their hidraw node, without hidapi. A virtual foot switch therefore
+//! exercises everything above the transport (selection, the protocol, and the
+//! emulator), but not hidapi itself.
+
+use std::io;
+use std::io::{Read, Write};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::process;
+use std::thread;
+use emulator::Emulator;
+use transport::Transport;
+
+const UHID_PATH: &str = "/dev/uhid";
+
+// Event types, see include/uapi/linux/uhid.h
+const UHID_OUTPUT: u32 = 6;
+const UHID_GET_REPORT: u32 = 9;
+const UHID_GET_REPORT_REPLY: u32 = 10;
+const UHID_CREATE2: u32 = 11;
+const UHID_INPUT2: u32 = 12;
+const UHID_SET_REPORT: u32 = 13;
+const UHID_SET_REPORT_REPLY: u32 = 14;
+
+const UHID_DATA_MAX: usize = 4096;
+
+// struct uhid_event is packed: a 4 byte type followed by the largest request,
+// which is uhid_create2_req (128 + 64 + 64 + 2 + 2 + 4 * 4 + UHID_DATA_MAX bytes)
+const UHID_EVENT_SIZE: usize = 4 + 276 + UHID_DATA_MAX;
+
+const BUS_USB: u16 = 0x03;
+
+const VENDOR_ID: u32 = 0x0c45;
+const PRODUCT_ID: u32 = 0x7403;
+
+/// Report descriptor of a boot keyboard (interface 0)
+static KEYBOARD_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,       // Usage Page (Generic Desktop)
+    0x09, 0x06,       // Usage (Keyboard)
+    0xa1, 0x01,       // Collection (Application)
+    0x05, 0x07,       //   Usage Page (Key Codes)
+    0x19, 0xe0,       //   Usage Minimum (224)
+    0x29, 0xe7,       //   Usage Maximum (231)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x25, 0x01,       //   Logical Maximum (1)
+    0x75, 0x01,       //   Report Size (1)
+    0x95, 0x08,       //   Report Count (8)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute), modifiers
+    0x95, 0x01,       //   Report Count (1)
+    0x75, 0x08,       //   Report Size (8)
+    0x81, 0x01,       //   Input (Constant), reserved byte
+    0x95, 0x06,       //   Report Count (6)
+    0x75, 0x08,       //   Report Size (8)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x26, 0xff, 0x00, //   Logical Maximum (255)
+    0x05, 0x07,       //   Usage Page (Key Codes)
+    0x19, 0x00,       //   Usage Minimum (0)
+    0x29, 0xff,       //   Usage Maximum (255)
+    0x81, 0x00,       //   Input (Data, Array), keys
+    0xc0,             // End Collection
+];
+
+/// Report descriptor of the vendor defined configuration channel (interface 1)
+static CONFIG_DESCRIPTOR: &[u8] = &[
+    0x06, 0x00, 0xff, // Usage Page (Vendor Defined 0xFF00)
+    0x09, 0x01,       // Usage (1)
+    0xa1, 0x01,       // Collection (Application)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x26, 0xff, 0x00, //   Logical Maximum (255)
+    0x75, 0x08,       //   Report Size (8)
+    0x95, 0x08,       //   Report Count (8)
+    0x09, 0x01,       //   Usage (1)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0x95, 0x08,       //   Report Count (8)
+    0x09, 0x01,       //   Usage (1)
+    0x91, 0x02,       //   Output (Data, Variable, Absolute)
+    0xc0,             // End Collection
+];
+
+/// A single virtual HID device, registered at /dev/uhid
+struct UhidDevice {
+    file: File,
+    phys: String,
+}
+
+impl UhidDevice {
+    fn create(name: &str, interface: u8, descriptor: &[u8]) -> io::Result<UhidDevice> {
+        let file = OpenOptions::new().read(true).write(true).open(UHID_PATH)?;
+
+        let phys = format!("footswitch-rs-{}/input{}", process::id(), interface);
+
+        let mut event = new_event(UHID_CREATE2);
+        put_str(&mut event[4..132], name);
+        put_str(&mut event[132..196], &phys);
+        put_str(&mut event[196..260], "");
+        event[260..262].copy_from_slice(&(descriptor.len() as u16).to_ne_bytes());
+        event[262..264].copy_from_slice(&BUS_USB.to_ne_bytes());
+        event[264..268].copy_from_slice(&VENDOR_ID.to_ne_bytes());
+        event[268..272].copy_from_slice(&PRODUCT_ID.to_ne_bytes());
+        event[280..(280 + descriptor.len())].copy_from_slice(descriptor);
+
+        let mut device = UhidDevice { file, phys };
+        device.file.write_all(&event)?;
+
+        Ok(device)
+    }
+
+    fn next_event(&mut self) -> io::Result<Vec<u8>> {
+        let mut event = vec![0u8; UHID_EVENT_SIZE];
+        self.file.read_exact(&mut event)?;
+
+        Ok(event)
+    }
+
+    fn send_input(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut event = new_event(UHID_INPUT2);
+        event[4..6].copy_from_slice(&(data.len() as u16).to_ne_bytes());
+        event[6..(6 + data.len())].copy_from_slice(data);
+
+        self.file.write_all(&event)
+    }
+
+    fn reply(&mut self, typ: u32, id: &[u8], err: u16) -> io::Result<()> {
+        let mut event = new_event(typ);
+        event[4..8].copy_from_slice(id);
+        event[8..10].copy_from_slice(&err.to_ne_bytes());
+
+        self.file.write_all(&event)
+    }
+
+    /// Search the hidraw node that the kernel created for this device
+    fn hidraw_node(&self) -> Option<String> {
+        let entries = fs::read_dir("/sys/class/hidraw").ok()?;
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let uevent = match fs::read_to_string(entry.path().join("device/uevent")) {
+                Ok(uevent) => uevent,
+                Err(_) => continue,
+            };
+
+            if uevent.lines().any(|line| line == format!("HID_PHYS={}", self.phys)) {
+                return Some(format!("/dev/{}", entry.file_name().to_string_lossy()));
+            }
+        }
+
+        None
+    }
+}
+
+/// Virtual foot switch, consisting of a keyboard and a configuration device
+pub struct VirtualPedal {
+    keyboard: UhidDevice,
+    config: UhidDevice,
+    emulator: Emulator,
+}
+
+impl VirtualPedal {
+    pub fn create(emulator: Emulator) -> io::Result<VirtualPedal> {
+        let keyboard = UhidDevice::create("PCsensor FootSwitch (virtual)", 0, KEYBOARD_DESCRIPTOR)?;
+        let config = UhidDevice::create("PCsensor FootSwitch (virtual)", 1, CONFIG_DESCRIPTOR)?;
+
+        Ok(VirtualPedal { keyboard, config, emulator })
+    }
+
+    /// hidraw nodes of the keyboard and the configuration interface
+    pub fn hidraw_nodes(&self) -> (Option<String>, Option<String>) {
+        (self.keyboard.hidraw_node(), self.config.hidraw_node())
+    }
+
+    /// Handle events until the device is destroyed or an error occurs
+    pub fn run(self) -> io::Result<()> {
+        let VirtualPedal { mut keyboard, mut config, emulator } = self;
+
+        // The keyboard only has to acknowledge the requests of the kernel
+        thread::spawn(move || -> io::Result<()> {
+            loop {
+                let event = keyboard.next_event()?;
+                handle_report_requests(&mut keyboard, &event)?;
+            }
+        });
+
+        loop {
+            let event = config.next_event()?;
+
+            match event_type(&event) {
+                UHID_OUTPUT => {
+                    let size = u16::from_ne_bytes([event[4 + UHID_DATA_MAX], event[5 + UHID_DATA_MAX]]) as usize;
+                    forward_to_emulator(&mut config, &emulator, &event[4..(4 + size)])?;
+                },
+                UHID_SET_REPORT => {
+                    let size = u16::from_ne_bytes([event[10], event[11]]) as usize;
+                    forward_to_emulator(&mut config, &emulator, &event[12..(12 + size)])?;
+                    config.reply(UHID_SET_REPORT_REPLY, &event[4..8], 0)?;
+                },
+                _ => handle_report_requests(&mut config, &event)?,
+            }
+        }
+    }
+}
+
+fn forward_to_emulator(config: &mut UhidDevice, emulator: &Emulator, packet: &[u8]) -> io::Result<()> {
+    // Packets that the firmware does not understand are not answered, just
+    // like a real device would do
+    if emulator.write(packet).is_err() {
+        return Ok(());
+    }
+
+    // Send all answers of the emulator back to the host
+    let mut buf = [0u8; 8];
+    while let Ok(len) = emulator.read(&mut buf) {
+        config.send_input(&buf[..len])?;
+    }
+
+    Ok(())
+}
+
+/// Feature reports are not supported, so answer GET_REPORT and SET_REPORT with EIO
+fn handle_report_requests(device: &mut UhidDevice, event: &[u8]) -> io::Result<()> {
+    const EIO: u16 = 5;
+
+    match event_type(event) {
+        UHID_GET_REPORT => device.reply(UHID_GET_REPORT_REPLY, &event[4..8], EIO),
+        UHID_SET_REPORT => device.reply(UHID_SET_REPORT_REPLY, &event[4..8], EIO),
+        _ => Ok(()),
+    }
+}
+
+fn new_event(typ: u32) -> Vec<u8> {
+    let mut event = vec![0u8; UHID_EVENT_SIZE];
+    event[0..4].copy_from_slice(&typ.to_ne_bytes());
+
+    event
+}
+
+fn event_type(event: &[u8]) -> u32 {
+    u32::from_ne_bytes([event[0], event[1], event[2], event[3]])
+}
+
+fn put_str(field: &mut [u8], value: &str) {
+    // Leave room for the terminating zero
+    let len = value.len().min(field.len() - 1);
+    field[..len].copy_from_slice(&value.as_bytes()[..len]);
+}
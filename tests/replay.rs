@@ -0,0 +1,53 @@
+//! Replays sessions that were recorded with `--record` and checks that
+//! footswitch-rs still sends the same packets and understands the responses.
+//! The sessions in `tests/sessions` come from the emulator, not from real
+//! hardware; their header names the command that recorded them.
+
+extern crate footswitch_rs;
+
+use std::process::{Command, Output};
+use footswitch_rs::Pedals;
+use footswitch_rs::session::Replay;
+
+fn replay(name: &str) -> Replay {
+    Replay::open(format!("{}/tests/sessions/{}.session", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+}
+
+fn describe_all(pedals: &Pedals<&Replay>) -> Vec<Option<String>> {
+    (0..3).map(|ped| pedals.describe_pedal(ped).unwrap()).collect()
+}
+
+#[test]
+fn read_all() {
+    let session = replay("read-all");
+    let pedals = Pedals::with_transport(&session);
+
+    assert_eq!(describe_all(&pedals), vec![
+        Some("ctrl+<b>".to_string()),
+        Some("<h><e><l><l><o>< ><w><o><r><l><d>".to_string()),
+        Some("right X = 0 Y = 0 W = 0".to_string()),
+    ]);
+
+    assert_eq!(session.remaining(), 0);
+}
+
+/// Runs footswitch-rs with the device replaced by a recorded session
+fn run_replay(name: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_footswitch-rs"))
+        .arg("--device")
+        .arg(format!("replay:{}/tests/sessions/{}.session", env!("CARGO_MANIFEST_DIR"), name))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn append_modifier() {
+    let output = run_replay("append-modifier", &["append", "modifier", "-p", "0", "-i", "shift"]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("shift+<b>"));
+
+    // Anything else than what was recorded makes the replay fail
+    assert!(!run_replay("append-modifier", &["append", "modifier", "-p", "0", "-i", "alt"]).status.success());
+}
@@ -0,0 +1,41 @@
+//! Creates a virtual foot switch through uhid and uses it like a real one,
+//! i.e., it is found through enumeration and its configuration interface is
+//! opened by `open_device`. Virtual devices are found through sysfs and opened
+//! through their hidraw node, so this does not cover hidapi. It needs the uhid
+//! module and write access to /dev/uhid, so run it with
+//! `cargo test -- --ignored` as root.
+
+#![cfg(target_os = "linux")]
+
+extern crate footswitch_rs;
+
+use std::thread;
+use std::time::Duration;
+use footswitch_rs::{DeviceSelector, DeviceTable, Pedals, list_devices, open_device};
+use footswitch_rs::emulator::Emulator;
+use footswitch_rs::uhid::VirtualPedal;
+
+#[test]
+#[ignore]
+fn virtual_device_is_enumerated_and_opened() {
+    let emulator = Emulator::new();
+    emulator.set_pedal_data(0, &[0x08, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00]);
+
+    let pedal = VirtualPedal::create(emulator).expect("Could not create virtual device");
+
+    // The kernel needs a moment to create the hidraw nodes
+    thread::sleep(Duration::from_millis(500));
+
+    let (_, config) = pedal.hidraw_nodes();
+    thread::spawn(move || pedal.run());
+
+    let table = DeviceTable::default();
+    let devices = list_devices(&table).unwrap();
+
+    assert!(devices.iter().any(|device| device.interface_number == 0 && device.vendor_id == "0c45"));
+    assert!(devices.iter().any(|device| device.config_interface && Some(&device.path) == config.as_ref()));
+
+    let pedals = Pedals::with_transport(open_device(&DeviceSelector::default(), &table).unwrap());
+
+    assert_eq!(pedals.describe_pedal(0).unwrap(), Some("<a>".to_string()));
+}